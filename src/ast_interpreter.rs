@@ -1,11 +1,27 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::bail;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, LogNormal, Normal, Triangular};
 
-use crate::parser::{
-    Atom::{self, Ident, Number},
-    Expr, Node, Operator, AST,
+use crate::{
+    diagnostic::{did_you_mean, Span},
+    parser::{
+        Atom::{self, Ident, Number},
+        Expr, Node, Operator, AST,
+    },
+    summation::{fsum, CompensatedSum},
+    table::Table,
 };
 
 /*
@@ -20,44 +36,592 @@ cell b:
 
 */
 
-pub type Params = HashMap<String, f64>;
+pub type Params = HashMap<String, Value>;
+
+/// Functions implemented by `run_expr`
+pub const BUILTINS: &[&str] = &[
+    "rand",
+    "uniform",
+    "normal",
+    "lognormal",
+    "triangular",
+    "round",
+    "floor",
+    "ceil",
+    "trunc",
+    "abs",
+    "sign",
+    "pow",
+    "approx_eq",
+    "sum",
+    "sum_exact",
+    "avg",
+    "min",
+    "max",
+    "clamp",
+    "tier_lookup",
+    "interp",
+    "trace",
+    "env",
+];
+
+/// Names `run_expr` still matches that aren't functions anymore, they fail
+/// with a pointer to their replacement
+const REMOVED: &[&str] = &["int"];
 pub type CallStack = Vec<String>;
 
+/// How deeply `run_expr` may recurse, also through the cells and periods
+/// an expression references. Past it evaluation fails with [`TooDeep`]
+/// instead of overflowing the stack.
+pub const MAX_EVALUATION_DEPTH: usize = 4096;
+
+/// A stack that fits `MAX_EVALUATION_DEPTH` levels, also in debug builds.
+/// The default stack of a thread fits far fewer.
+pub const STACK_SIZE: usize = 64 << 20;
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+        }
+    }
+
+    pub fn as_number(&self) -> Result<f64, anyhow::Error> {
+        match self {
+            Value::Number(x) => Ok(*x),
+            x => bail!("expected a number, found {} {}", x.type_name(), x),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&[Value], anyhow::Error> {
+        match self {
+            Value::List(x) => Ok(x),
+            x => bail!("expected a list, found {} {}", x.type_name(), x),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, anyhow::Error> {
+        match self {
+            Value::Bool(x) => Ok(*x),
+            x => bail!("expected a bool, found {} {}", x.type_name(), x),
+        }
+    }
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(x) => write!(f, "{:?}", x),
+            Value::String(x) => write!(f, "{:?}", x),
+            Value::Bool(x) => write!(f, "{:?}", x),
+            Value::List(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(x) => write!(f, "{}", x),
+            Value::String(x) => write!(f, "{:?}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::List(x) => {
+                write!(f, "[")?;
+                for (i, item) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Number(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(x: bool) -> Self {
+        Value::Bool(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(x: &str) -> Self {
+        Value::String(x.to_string())
+    }
+}
+
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        matches!(self, Value::Number(x) if x == other)
+    }
+}
+
+/// A cheap, cloneable flag used to abort a running evaluation from another
+/// thread. The interpreter checks it before evaluating every expression.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Collects the values passed to `trace(x)`. Like [`CancellationToken`] it's
+/// shared, the caller keeps a clone to read the entries after the run.
+#[derive(Debug, Clone, Default)]
+pub struct TraceBuffer(Arc<Mutex<Vec<TraceEntry>>>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// the cell whose expression called `trace`
+    pub cell: String,
+    pub value: Value,
+    /// span of the cell
+    pub span: Option<Span>,
+}
+
+impl TraceBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, entry: TraceEntry) {
+        self.0.lock().unwrap().push(entry);
+    }
+
+    /// The entries recorded so far, in evaluation order, leaving the buffer
+    /// empty
+    pub fn take(&self) -> Vec<TraceEntry> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+/// Collects the failed `assert`s of a run with
+/// [`RunOptions::assert_warnings`], shared like [`TraceBuffer`].
+#[derive(Debug, Clone, Default)]
+pub struct WarningBuffer(Arc<Mutex<Vec<String>>>);
+
+impl WarningBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, warning: String) {
+        self.0.lock().unwrap().push(warning);
+    }
+
+    /// The warnings recorded so far, leaving the buffer empty
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+type NumericFn = dyn Fn(&[f64]) -> f64 + Send + Sync;
+
+/// A numeric function provided by the embedder, called from a model like a
+/// builtin, e.g. `region_price(x)`
+#[derive(Clone)]
+pub struct HostFunction {
+    pub arity: usize,
+    function: Arc<NumericFn>,
+}
+
+impl HostFunction {
+    /// `function` is given exactly `arity` arguments
+    pub fn new(arity: usize, function: impl Fn(&[f64]) -> f64 + Send + Sync + 'static) -> Self {
+        Self {
+            arity,
+            function: Arc::new(function),
+        }
+    }
+}
+
+impl std::fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostFunction({} args)", self.arity)
+    }
+}
+
+/// The error of an evaluation that ran past `RunOptions::deadline`, with
+/// the queried cells that completed before it, in query order
+#[derive(Debug)]
+pub struct TimedOut {
+    pub timeout: Duration,
+    pub completed: Vec<(String, Value)>,
+}
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "evaluation timed out after {:?}", self.timeout)
+    }
+}
+
+/// `error`, with the `completed` cells if it is a [`TimedOut`]
+fn with_completed(error: anyhow::Error, completed: &[(String, Value)]) -> anyhow::Error {
+    match error.downcast::<TimedOut>() {
+        Ok(timed_out) => TimedOut {
+            completed: completed.to_vec(),
+            ..timed_out
+        }
+        .into(),
+        Err(error) => error,
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The error of an evaluation that evaluated more expressions than
+/// `RunOptions::fuel` allows
+#[derive(Debug)]
+pub struct OutOfFuel(pub u64);
+
+impl Display for OutOfFuel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "evaluation ran out of fuel after {} steps", self.0)
+    }
+}
+
+impl std::error::Error for OutOfFuel {}
+
+/// The error of an evaluation nested deeper than `MAX_EVALUATION_DEPTH`,
+/// with the cell (or period, e.g. `balance[3]`) it was about to evaluate
+#[derive(Debug)]
+pub struct TooDeep(pub String);
+
+impl Display for TooDeep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "evaluating `{}` nests more than {} levels deep through the cells it references",
+            self.0, MAX_EVALUATION_DEPTH
+        )
+    }
+}
+
+impl std::error::Error for TooDeep {}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    pub cancellation: CancellationToken,
+    /// `(start, timeout)`, evaluation fails with [`TimedOut`] once `timeout`
+    /// has passed since `start`
+    pub deadline: Option<(Instant, Duration)>,
+    /// how many expressions an evaluation may evaluate, it fails with
+    /// [`OutOfFuel`] past that. Bounds untrusted models independently of
+    /// the speed of the machine.
+    pub fuel: Option<u64>,
+    /// tables available to `tier_lookup(x, name)`
+    pub tables: HashMap<String, Table>,
+    /// seeds the RNG behind `rand()` and the distribution builtins, runs
+    /// without a seed are not reproducible
+    pub seed: Option<u64>,
+    /// evaluate chains of `+` and `-` with compensated summation
+    pub compensated_sum: bool,
+    /// where failed `assert`s are recorded as warnings instead of failing
+    /// the run
+    pub assert_warnings: Option<WarningBuffer>,
+    /// where `trace(x)` records its values, it only passes them through
+    /// without one
+    pub trace: Option<TraceBuffer>,
+    /// functions registered with [`RunOptions::register`]
+    pub functions: HashMap<String, HostFunction>,
+}
+
+impl RunOptions {
+    /// Makes `function` callable by `name` from the model. Builtins, and
+    /// removed builtins, can't be replaced.
+    pub fn register(&mut self, name: &str, function: HostFunction) -> Result<(), anyhow::Error> {
+        if BUILTINS.contains(&name) || REMOVED.contains(&name) {
+            bail!("`{}` is a builtin and can't be registered", name);
+        }
+        self.functions.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    /// Options whose RNG produces an independent, but still reproducible,
+    /// stream of numbers. `n` identifies the stream, e.g. the index of a
+    /// permutation.
+    pub fn stream(&self, n: u64) -> Self {
+        Self {
+            seed: self
+                .seed
+                .map(|seed| ChaCha8Rng::seed_from_u64(seed.wrapping_add(n)).gen()),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CellResult<'a> {
     Pending(&'a Expr),
-    Done(f64),
+    Done(Value),
+    /// `cell name[index]: expr`, evaluated once per period
+    Indexed {
+        index: &'a str,
+        expr: &'a Expr,
+    },
 }
 
 #[derive(Debug)]
 pub struct ExecutionContext<'a> {
     pub cell_results: HashMap<&'a str, CellResult<'a>>,
     pub call_stack: RefCell<CallStack>,
+    pub options: &'a RunOptions,
+    /// set by `@eq-tolerance(x)`, `==` holds when the operands are at most `x` apart
+    pub eq_tolerance: f64,
+    /// set by `@periods(n)`, the number of values of time-indexed cells
+    pub periods: Option<usize>,
+    /// the period of the time-indexed cell being evaluated, by the name it
+    /// has in that cell
+    pub period: Option<(&'a str, usize)>,
+    /// values of time-indexed cells, by cell and period
+    pub period_results: HashMap<(&'a str, usize), Value>,
+    /// the periods being evaluated, e.g. `balance[3]`, to catch cycles
+    pub period_stack: Vec<String>,
+    /// set by `@iterate(max_iterations, epsilon)`, circular references are
+    /// solved by iterating to a fixed point instead of failing
+    pub iteration: Option<(usize, f64)>,
+    /// values of the cells from the previous iteration, cells in a cycle
+    /// read these instead of recursing
+    pub guesses: HashMap<String, Value>,
+    /// values of the cells in the current iteration
+    pub iterates: HashMap<String, Value>,
+    /// whether a circular reference was cut short in the current iteration
+    pub cyclic: bool,
+    /// spans of the cells, for trace entries
+    pub spans: HashMap<&'a str, Span>,
+    pub rng: ChaCha8Rng,
+    /// number of `run_expr` calls so far, checked against `RunOptions::fuel`
+    pub steps: u64,
+    /// how deeply `run_expr` is nested, checked against `MAX_EVALUATION_DEPTH`
+    pub depth: usize,
+    /// number of `run_expr` calls, reported to the telemetry sink
+    #[cfg(feature = "telemetry")]
+    pub evaluated: usize,
 }
 
-impl<'a> Default for ExecutionContext<'a> {
-    fn default() -> Self {
+impl<'a> ExecutionContext<'a> {
+    pub fn new(options: &'a RunOptions) -> Self {
         Self {
             cell_results: Default::default(),
             call_stack: Vec::with_capacity(10).into(),
+            options,
+            eq_tolerance: 0.0,
+            periods: None,
+            period: None,
+            period_results: HashMap::new(),
+            period_stack: vec![],
+            iteration: None,
+            guesses: HashMap::new(),
+            iterates: HashMap::new(),
+            cyclic: false,
+            spans: HashMap::new(),
+            rng: match options.seed {
+                Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+                None => ChaCha8Rng::from_rng(rand::thread_rng()).expect("thread_rng never fails"),
+            },
+            steps: 0,
+            depth: 0,
+            #[cfg(feature = "telemetry")]
+            evaluated: 0,
         }
     }
-}
 
-impl<'a> ExecutionContext<'a> {
     pub fn find_cell(&self, cell_name: &str) -> Result<&CellResult<'a>, anyhow::Error> {
         if let Some(cell) = self.cell_results.get(cell_name) {
             Ok(cell)
         } else {
-            bail!("`{}` is not defined", cell_name);
+            match did_you_mean(cell_name, self.cell_results.keys().copied()) {
+                Some(x) => bail!("`{}` is not defined, did you mean `{}`?", cell_name, x),
+                None => bail!("`{}` is not defined", cell_name),
+            }
+        }
+    }
+}
+
+pub(crate) fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
+    a == b || (a - b).abs() <= eps
+}
+
+fn run_number(expr: &Expr, context: &mut ExecutionContext) -> Result<f64, anyhow::Error> {
+    run_expr(expr, context)?.as_number()
+}
+
+fn run_bool(expr: &Expr, context: &mut ExecutionContext) -> Result<bool, anyhow::Error> {
+    run_expr(expr, context)?.as_bool()
+}
+
+/// Flattens a chain of `+` and `-` into its terms, in evaluation order,
+/// paired with whether the term is subtracted
+fn additive_terms<'a>(expr: &'a Expr, negate: bool, terms: &mut Vec<(&'a Expr, bool)>) {
+    match expr {
+        Expr::Add(l, r) => {
+            additive_terms(l, negate, terms);
+            additive_terms(r, negate, terms);
+        }
+        Expr::Sub(l, r) => {
+            additive_terms(l, negate, terms);
+            additive_terms(r, !negate, terms);
+        }
+        x => terms.push((x, negate)),
+    }
+}
+
+/// Evaluates the arguments of a builtin that takes exactly `arity` numbers
+fn run_number_args(
+    name: &str,
+    arity: usize,
+    arguments: &[Expr],
+    context: &mut ExecutionContext,
+) -> Result<Vec<f64>, anyhow::Error> {
+    if arguments.len() != arity {
+        bail!("{}() expects {} args", name, arity)
+    }
+    arguments
+        .iter()
+        .map(|arg| run_number(arg, context))
+        .collect()
+}
+
+/// Evaluates the only argument of an aggregate builtin to a list of numbers
+fn run_aggregate_arg(
+    name: &str,
+    arguments: &[Expr],
+    context: &mut ExecutionContext,
+) -> Result<Vec<f64>, anyhow::Error> {
+    if arguments.len() != 1 {
+        bail!("{}() expects 1 arg: a list", name)
+    }
+    run_expr(&arguments[0], context)?
+        .as_list()?
+        .iter()
+        .map(Value::as_number)
+        .collect()
+}
+
+fn compare(
+    op: &Operator,
+    lhs: &Value,
+    rhs: &Value,
+    eq_tolerance: f64,
+) -> Result<bool, anyhow::Error> {
+    if let Operator::NotEquals = op {
+        return Ok(!compare(&Operator::Equals, lhs, rhs, eq_tolerance)?);
+    }
+    let r = match (op, lhs, rhs) {
+        (Operator::Equals, Value::Number(lhs), Value::Number(rhs)) => {
+            approx_eq(*lhs, *rhs, eq_tolerance)
+        }
+        (Operator::Equals, Value::String(lhs), Value::String(rhs)) => lhs == rhs,
+        (Operator::Equals, Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+        (Operator::Equals, Value::List(lhs), Value::List(rhs)) => {
+            if lhs.len() != rhs.len() {
+                return Ok(false);
+            }
+            for (lhs, rhs) in lhs.iter().zip(rhs) {
+                if !compare(op, lhs, rhs, eq_tolerance)? {
+                    return Ok(false);
+                }
+            }
+            true
         }
+        (Operator::Equals, lhs, rhs) => bail!(
+            "cannot compare {} {} with {} {}",
+            lhs.type_name(),
+            lhs,
+            rhs.type_name(),
+            rhs
+        ),
+        (op, lhs, rhs) => {
+            let (lhs, rhs) = (lhs.as_number()?, rhs.as_number()?);
+            match op {
+                Operator::Greater => lhs > rhs,
+                Operator::GreaterEqual => lhs >= rhs,
+                Operator::Less => lhs < rhs,
+                Operator::LessEqual => lhs <= rhs,
+                Operator::Equals | Operator::NotEquals => unreachable!(),
+            }
+        }
+    };
+    Ok(r)
+}
+
+pub fn run_expr(expr: &Expr, context: &mut ExecutionContext) -> Result<Value, anyhow::Error> {
+    context.depth += 1;
+    let result = evaluate(expr, context);
+    context.depth -= 1;
+    result
+}
+
+/// Fails with [`TooDeep`] before evaluating `cell` past
+/// `MAX_EVALUATION_DEPTH`. One cell nests at most `HARD_MAX_DEPTH` levels,
+/// so checking when entering a cell is enough.
+fn check_depth(cell: &str, context: &ExecutionContext) -> Result<(), TooDeep> {
+    if context.depth > MAX_EVALUATION_DEPTH {
+        return Err(TooDeep(cell.to_string()));
     }
+    Ok(())
 }
 
-pub fn run_expr(expr: &Expr, context: &mut ExecutionContext) -> Result<f64, anyhow::Error> {
+fn evaluate(expr: &Expr, context: &mut ExecutionContext) -> Result<Value, anyhow::Error> {
+    if context.options.cancellation.is_cancelled() {
+        bail!("evaluation cancelled");
+    }
+    if let Some((start, timeout)) = context.options.deadline {
+        if start.elapsed() > timeout {
+            return Err(TimedOut {
+                timeout,
+                completed: vec![],
+            }
+            .into());
+        }
+    }
+    context.steps += 1;
+    if let Some(fuel) = context.options.fuel {
+        if context.steps > fuel {
+            return Err(OutOfFuel(fuel).into());
+        }
+    }
+    #[cfg(feature = "telemetry")]
+    {
+        context.evaluated += 1;
+    }
     let result = match expr {
         Expr::Atom(x) => match x {
-            Number(x) => Ok(*x),
+            Number(x) => Ok(Value::Number(*x)),
+            Atom::String(x) => Ok(Value::String(x.clone())),
+            Atom::Bool(x) => Ok(Value::Bool(*x)),
+            Ident(name) if matches!(context.period, Some((index, _)) if index == name) => {
+                Ok(Value::Number(context.period.unwrap().1 as f64))
+            }
             Ident(cell_name) => {
                 if context
                     .call_stack
@@ -66,108 +630,596 @@ pub fn run_expr(expr: &Expr, context: &mut ExecutionContext) -> Result<f64, anyh
                     .find(|x| *x == cell_name)
                     .is_some()
                 {
+                    if context.iteration.is_some() {
+                        context.cyclic = true;
+                        let guess = context.guesses.get(cell_name).cloned();
+                        return Ok(guess.unwrap_or(Value::Number(0.0)));
+                    }
                     bail!(
                         "cyclic dependency found. {:?} -> {}",
                         context.call_stack,
                         cell_name
                     )
                 }
-                let cell = context.find_cell(cell_name)?;
+                let cell = match context.find_cell(cell_name)? {
+                    CellResult::Pending(x) => CellResult::Pending(x),
+                    CellResult::Done(x) => CellResult::Done(x.clone()),
+                    CellResult::Indexed { index, expr } => CellResult::Indexed { index, expr },
+                };
                 let result = match cell {
                     CellResult::Pending(x) => {
+                        check_depth(cell_name, context)?;
                         context.call_stack.try_borrow_mut()?.push(cell_name.clone());
-                        run_expr(x, context)?
+                        // the period of a time-indexed cell isn't visible
+                        // in the cells it references
+                        let period = context.period.take();
+                        let result = run_expr(x, context);
+                        context.period = period;
+                        context.call_stack.try_borrow_mut()?.pop();
+                        let result = result?;
+                        if context.iteration.is_some() {
+                            context.iterates.insert(cell_name.clone(), result.clone());
+                        }
+                        result
                     }
-                    CellResult::Done(x) => *x,
+                    CellResult::Done(x) => x,
+                    CellResult::Indexed { .. } => run_periods(cell_name, context)?,
                 };
                 Ok(result)
             }
             Atom::Call { name, arguments } => match name.as_str() {
-                "rand" => {
-                    let mut rng = rand::thread_rng();
-                    Ok(rng.gen())
+                "rand" | "uniform" => {
+                    // rand() samples [0, 1), rand(a, b) and uniform(a, b) sample [a, b)
+                    let (a, b) = match (name.as_str(), arguments.len()) {
+                        ("rand", 0) => (0.0, 1.0),
+                        _ => match run_number_args(name, 2, arguments, context)?[..] {
+                            [a, b] if a <= b => (a, b),
+                            [a, b] => bail!("{}() lower bound {} is above {}", name, a, b),
+                            _ => unreachable!(),
+                        },
+                    };
+                    Ok(Value::Number(a + (b - a) * context.rng.gen::<f64>()))
+                }
+                "normal" | "lognormal" => {
+                    let args = run_number_args(name, 2, arguments, context)?;
+                    let (mu, sigma) = (args[0], args[1]);
+                    if sigma < 0.0 {
+                        bail!("{}() sigma must not be negative, found {}", name, sigma)
+                    }
+                    let rng = &mut context.rng;
+                    let sample = match name.as_str() {
+                        "normal" => Normal::new(mu, sigma).map(|x| x.sample(rng)),
+                        _ => LogNormal::new(mu, sigma).map(|x| x.sample(rng)),
+                    };
+                    match sample {
+                        Ok(x) => Ok(Value::Number(x)),
+                        Err(e) => bail!("{}({}, {}): {}", name, mu, sigma, e),
+                    }
                 }
-                "int" => {
+                "triangular" => {
+                    // triangular(min, mode, max)
+                    let args = run_number_args(name, 3, arguments, context)?;
+                    let (min, mode, max) = (args[0], args[1], args[2]);
+                    match Triangular::new(min, max, mode) {
+                        Ok(x) => Ok(Value::Number(x.sample(&mut context.rng))),
+                        Err(e) => bail!("triangular({}, {}, {}): {}", min, mode, max, e),
+                    }
+                }
+                "round" | "floor" | "ceil" | "trunc" | "abs" | "sign" => {
                     if arguments.len() != 1 {
-                        bail!("int() expects 1 arg")
+                        bail!("{}() expects 1 arg", name)
+                    }
+                    let x = run_number(&arguments[0], context)?;
+                    let result = match name.as_str() {
+                        // halfway cases round away from zero: round(-2.5) == -3
+                        "round" => x.round(),
+                        "floor" => x.floor(),
+                        "ceil" => x.ceil(),
+                        "trunc" => x.trunc(),
+                        "abs" => x.abs(),
+                        // unlike f64::signum, sign(0) == 0
+                        _ if x == 0.0 => 0.0,
+                        _ => x.signum(),
+                    };
+                    Ok(Value::Number(result))
+                }
+                "int" => bail!("int() was removed, use round(), floor(), ceil() or trunc()"),
+                "pow" => {
+                    if arguments.len() != 2 {
+                        bail!("pow() expects 2 args")
+                    }
+                    let base = run_number(&arguments[0], context)?;
+                    let exponent = run_number(&arguments[1], context)?;
+                    Ok(Value::Number(base.powf(exponent)))
+                }
+                "approx_eq" => {
+                    if arguments.len() != 3 {
+                        bail!("approx_eq() expects 3 args")
+                    }
+                    let a = run_number(&arguments[0], context)?;
+                    let b = run_number(&arguments[1], context)?;
+                    let eps = run_number(&arguments[2], context)?;
+                    Ok(Value::Bool(approx_eq(a, b, eps)))
+                }
+                "sum" => {
+                    let items = run_aggregate_arg("sum", arguments, context)?;
+                    // `Iterator::sum` of no floats is -0.0
+                    Ok(Value::Number(items.iter().fold(0.0, |a, x| a + x)))
+                }
+                "sum_exact" => {
+                    let items = run_aggregate_arg("sum_exact", arguments, context)?;
+                    Ok(Value::Number(fsum(items)))
+                }
+                "avg" => {
+                    let items = run_aggregate_arg("avg", arguments, context)?;
+                    if items.is_empty() {
+                        bail!("avg() of an empty list")
+                    }
+                    Ok(Value::Number(
+                        items.iter().sum::<f64>() / items.len() as f64,
+                    ))
+                }
+                "min" | "max" => {
+                    // min(costs) aggregates a list, min(a, b, c) its arguments
+                    let items = if arguments.len() > 1 {
+                        arguments
+                            .iter()
+                            .map(|arg| run_number(arg, context))
+                            .collect::<Result<_, _>>()?
+                    } else {
+                        run_aggregate_arg(name, arguments, context)?
+                    };
+                    let fold = if name == "min" { f64::min } else { f64::max };
+                    match items.into_iter().reduce(fold) {
+                        Some(x) => Ok(Value::Number(x)),
+                        None => bail!("{}() of an empty list", name),
+                    }
+                }
+                "clamp" => {
+                    if arguments.len() != 3 {
+                        bail!("clamp() expects 3 args: a value, a lower and an upper bound")
+                    }
+                    let x = run_number(&arguments[0], context)?;
+                    let lo = run_number(&arguments[1], context)?;
+                    let hi = run_number(&arguments[2], context)?;
+                    if lo > hi {
+                        bail!("clamp() lower bound {} is above the upper bound {}", lo, hi)
+                    }
+                    Ok(Value::Number(x.clamp(lo, hi)))
+                }
+                "tier_lookup" => {
+                    let table_name = match arguments.as_slice() {
+                        [_, Expr::Atom(Ident(table_name))] => table_name,
+                        _ => bail!("tier_lookup() expects 2 args: a value and a table name"),
+                    };
+                    let table = match context.options.tables.get(table_name) {
+                        Some(table) => table,
+                        None => bail!("table `{}` is not loaded", table_name),
+                    };
+                    let x = run_number(&arguments[0], context)?;
+                    match table.lookup(x) {
+                        Some(value) => Ok(Value::Number(value)),
+                        None => bail!("{} is below the first tier of `{}`", x, table_name),
+                    }
+                }
+                "trace" => {
+                    if arguments.len() != 1 {
+                        bail!("trace() expects 1 arg")
+                    }
+                    let value = run_expr(&arguments[0], context)?;
+                    if let Some(trace) = &context.options.trace {
+                        let cell = match context.period_stack.last() {
+                            Some(label) if context.period.is_some() => label.clone(),
+                            _ => context
+                                .call_stack
+                                .try_borrow()?
+                                .last()
+                                .cloned()
+                                .unwrap_or_default(),
+                        };
+                        let name = cell.split('[').next().unwrap_or_default();
+                        trace.push(TraceEntry {
+                            span: context.spans.get(name).cloned(),
+                            cell,
+                            value: value.clone(),
+                        });
+                    }
+                    Ok(value)
+                }
+                "env" => {
+                    bail!("`env()` must be resolved before evaluation, see `simplify::resolve_env`")
+                }
+                "interp" => match arguments.as_slice() {
+                    // interp(x, curve) reads a `--table` as the points of a curve
+                    [x, Expr::Atom(Ident(table_name))]
+                        if context.options.tables.contains_key(table_name) =>
+                    {
+                        let x = run_number(x, context)?;
+                        Ok(Value::Number(
+                            context.options.tables[table_name].interpolate(x),
+                        ))
+                    }
+                    [_, Expr::Atom(Ident(table_name))] => {
+                        bail!("table `{}` is not loaded", table_name)
+                    }
+                    _ => {
+                        // interp(x, x0, y0, x1, y1), extrapolates outside [x0, x1]
+                        let args = run_number_args(name, 5, arguments, context)?;
+                        let (x, x0, y0, x1, y1) = (args[0], args[1], args[2], args[3], args[4]);
+                        if x0 == x1 {
+                            bail!(
+                                "interp() needs two different points, both are at x = {}",
+                                x0
+                            )
+                        }
+                        Ok(Value::Number(y0 + (x - x0) * (y1 - y0) / (x1 - x0)))
+                    }
+                },
+                x if context.options.functions.contains_key(x) => {
+                    let function = &context.options.functions[x];
+                    let args = run_number_args(name, function.arity, arguments, context)?;
+                    Ok(Value::Number((function.function)(&args)))
+                }
+                x => {
+                    let known = BUILTINS
+                        .iter()
+                        .copied()
+                        .chain(context.options.functions.keys().map(String::as_str));
+                    match did_you_mean(x, known) {
+                        Some(builtin) => {
+                            bail!("undefined function {}, did you mean `{}`?", x, builtin)
+                        }
+                        None => bail!("undefined function {}", x),
                     }
-                    let arg = run_expr(&arguments[0], context)?;
-                    Ok(arg.round())
                 }
-                x => bail!("undefined function {}", x),
             },
         },
-        Expr::Add(l, r) => Ok(run_expr(l, context)? + run_expr(r, context)?),
-        Expr::Sub(l, r) => Ok(run_expr(l, context)? - run_expr(r, context)?),
-        Expr::Mul(l, r) => Ok(run_expr(l, context)? * run_expr(r, context)?),
-        Expr::Div(l, r) => Ok(run_expr(l, context)? / run_expr(r, context)?),
-        Expr::Mod(l, r) => Ok(run_expr(l, context)? % run_expr(r, context)?),
+        Expr::Add(..) | Expr::Sub(..) if context.options.compensated_sum => {
+            let mut terms = vec![];
+            additive_terms(expr, false, &mut terms);
+            let mut sum = CompensatedSum::default();
+            for (term, negate) in terms {
+                let x = run_number(term, context)?;
+                sum.add(if negate { -x } else { x });
+            }
+            Ok(Value::Number(sum.total()))
+        }
+        Expr::Add(l, r) => Ok(Value::Number(
+            run_number(l, context)? + run_number(r, context)?,
+        )),
+        Expr::Sub(l, r) => Ok(Value::Number(
+            run_number(l, context)? - run_number(r, context)?,
+        )),
+        Expr::Mul(l, r) => Ok(Value::Number(
+            run_number(l, context)? * run_number(r, context)?,
+        )),
+        Expr::Div(l, r) => Ok(Value::Number(
+            run_number(l, context)? / run_number(r, context)?,
+        )),
+        Expr::Mod(l, r) => Ok(Value::Number(
+            run_number(l, context)? % run_number(r, context)?,
+        )),
+        Expr::Pow(l, r) => Ok(Value::Number(
+            run_number(l, context)?.powf(run_number(r, context)?),
+        )),
+        Expr::Compare(op, lhs, rhs) => {
+            let lhs = run_expr(lhs, context)?;
+            let rhs = run_expr(rhs, context)?;
+            Ok(Value::Bool(compare(op, &lhs, &rhs, context.eq_tolerance)?))
+        }
+        Expr::And(l, r) => Ok(Value::Bool(run_bool(l, context)? && run_bool(r, context)?)),
+        Expr::Or(l, r) => Ok(Value::Bool(run_bool(l, context)? || run_bool(r, context)?)),
+        Expr::Not(x) => Ok(Value::Bool(!run_bool(x, context)?)),
+        Expr::Neg(x) => Ok(Value::Number(-run_number(x, context)?)),
+        Expr::List(items) => {
+            let items = items
+                .iter()
+                .map(|item| run_expr(item, context))
+                .collect::<Result<_, _>>()?;
+            Ok(Value::List(items))
+        }
+        Expr::Index(list, index) if is_indexed_cell(list, context) => {
+            let t = run_number(index, context)?;
+            match list.as_ref() {
+                Expr::Atom(Ident(name)) => run_period(name, t, context),
+                _ => unreachable!(),
+            }
+        }
+        Expr::Index(list, index) => {
+            let list = match run_expr(list, context)? {
+                Value::List(x) => x,
+                x => bail!("cannot index into {} {}", x.type_name(), x),
+            };
+            let index = run_number(index, context)?;
+            if index.fract() != 0.0 || index < 0.0 || index as usize >= list.len() {
+                bail!(
+                    "index {} is out of bounds for a list of {}",
+                    index,
+                    list.len()
+                );
+            }
+            Ok(list[index as usize].clone())
+        }
         Expr::Condition {
-            lhs,
-            rhs,
-            op,
+            cond,
             true_branch,
             false_branch,
         } => {
-            let lhs = run_expr(lhs, context)?;
-            let rhs = run_expr(rhs, context)?;
-            let r = match op {
-                Operator::Equals => lhs == rhs,
-                Operator::Greater => lhs > rhs,
-                Operator::GreaterEqual => lhs >= rhs,
-                Operator::Less => lhs < rhs,
-                Operator::LessEqual => lhs <= rhs,
-            };
-            if r {
+            if run_bool(cond, context)? {
                 run_expr(true_branch, context)
             } else {
                 run_expr(false_branch, context)
             }
         }
     };
-    context.call_stack.try_borrow_mut()?.pop();
     result
 }
 
+/// Whether `expr` names a time-indexed cell, which is indexed by period
+/// instead of evaluated as a list
+fn is_indexed_cell(expr: &Expr, context: &ExecutionContext) -> bool {
+    match expr {
+        Expr::Atom(Ident(name)) => {
+            !matches!(context.period, Some((index, _)) if index == name)
+                && matches!(
+                    context.cell_results.get(name.as_str()),
+                    Some(CellResult::Indexed { .. })
+                )
+        }
+        _ => false,
+    }
+}
+
+/// The value of the time-indexed cell `name` in period `t`
+fn run_period<'a>(
+    name: &str,
+    t: f64,
+    context: &mut ExecutionContext<'a>,
+) -> Result<Value, anyhow::Error> {
+    let periods = match context.periods {
+        Some(x) => x,
+        None => bail!(
+            "time-indexed cell `{}` needs the number of periods, e.g. `@periods(12)`",
+            name
+        ),
+    };
+    if t.fract() != 0.0 || t < 0.0 || t as usize >= periods {
+        bail!(
+            "`{}[{}]` is out of range, the model has {} periods",
+            name,
+            t,
+            periods
+        );
+    }
+    let t = t as usize;
+    let (name, index, expr) = match context.cell_results.get_key_value(name) {
+        Some((name, CellResult::Indexed { index, expr })) => (*name, *index, *expr),
+        _ => bail!("`{}` is not a time-indexed cell", name),
+    };
+    if let Some(value) = context.period_results.get(&(name, t)) {
+        return Ok(value.clone());
+    }
+
+    let label = format!("{}[{}]", name, t);
+    if context.period_stack.contains(&label) {
+        bail!(
+            "cyclic dependency found. {:?} -> {}",
+            context.period_stack,
+            label
+        )
+    }
+    check_depth(&label, context)?;
+    context.period_stack.push(label);
+    let period = context.period.replace((index, t));
+    let result = run_expr(expr, context);
+    context.period = period;
+    context.period_stack.pop();
+    let value = result?;
+    context.period_results.insert((name, t), value.clone());
+    Ok(value)
+}
+
+/// Every period of the time-indexed cell `name`, as a list
+fn run_periods(name: &str, context: &mut ExecutionContext) -> Result<Value, anyhow::Error> {
+    let periods = context.periods.unwrap_or(0).max(1);
+    let values = (0..periods)
+        .map(|t| run_period(name, t as f64, context))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::List(values))
+}
+
+fn run_cells(
+    cell_names: &[&str],
+    context: &mut ExecutionContext,
+) -> Result<Vec<(String, Value)>, anyhow::Error> {
+    let mut results = vec![];
+    for cell_name in cell_names {
+        if context.options.cancellation.is_cancelled() {
+            bail!("evaluation cancelled");
+        }
+        let cell = context.find_cell(cell_name)?;
+        context
+            .call_stack
+            .try_borrow_mut()?
+            .push(cell_name.to_string());
+
+        let result = match cell {
+            CellResult::Pending(x) => run_expr(x, context),
+            CellResult::Done(x) => Ok(x.clone()),
+            CellResult::Indexed { .. } => run_periods(cell_name, context),
+        };
+        let result = match result {
+            Ok(result) => result,
+            // the values of an unfinished iteration aren't results yet
+            Err(e) if context.iteration.is_none() => return Err(with_completed(e, &results)),
+            Err(e) => return Err(e),
+        };
+        context.call_stack.try_borrow_mut()?.pop();
+        if context.iteration.is_some() {
+            context
+                .iterates
+                .insert(cell_name.to_string(), result.clone());
+        }
+        results.push((cell_name.to_string(), result))
+    }
+    Ok(results)
+}
+
+/// Whether every cell moved at most `epsilon` since the previous iteration
+fn converged(
+    previous: &HashMap<String, Value>,
+    current: &HashMap<String, Value>,
+    epsilon: f64,
+) -> bool {
+    current
+        .iter()
+        .all(|(name, value)| match (previous.get(name), value) {
+            (Some(Value::Number(a)), Value::Number(b)) => approx_eq(*a, *b, epsilon),
+            (Some(a), b) => a == b,
+            (None, _) => false,
+        })
+}
+
+/// Evaluates `cell_names` and returns their values in the same order as they
+/// were queried. Call it on a thread with a [`STACK_SIZE`] stack, a deep
+/// model can overflow a smaller one before [`MAX_EVALUATION_DEPTH`].
 pub fn run(
     code: &AST,
     cell_names: &[&str],
     params: &Params,
-) -> Result<Vec<(String, f64)>, anyhow::Error> {
-    let mut context = ExecutionContext::default();
+) -> Result<Vec<(String, Value)>, anyhow::Error> {
+    run_with_options(code, cell_names, params, &RunOptions::default())
+}
+
+/// [`run`] with `options`. It needs the same [`STACK_SIZE`] stack.
+pub fn run_with_options(
+    code: &AST,
+    cell_names: &[&str],
+    params: &Params,
+    options: &RunOptions,
+) -> Result<Vec<(String, Value)>, anyhow::Error> {
+    #[cfg(feature = "telemetry")]
+    let started = std::time::Instant::now();
+    let mut context = ExecutionContext::new(options);
+    let mut asserts = vec![];
     for node in &code.nodes {
         match node {
             Node::Cell(cell) => {
-                context
-                    .cell_results
-                    .insert(&cell.name, CellResult::Pending(&cell.expr));
+                let result = match &cell.index {
+                    Some(index) => CellResult::Indexed {
+                        index,
+                        expr: &cell.expr,
+                    },
+                    None => CellResult::Pending(&cell.expr),
+                };
+                context.cell_results.insert(&cell.name, result);
+                context.spans.insert(&cell.name, cell.span.clone());
             }
-            Node::Param(value) => {
-                let name = &value.name;
+            Node::Param(param) => {
+                let name = &param.name;
                 if let Some(value) = params.get(name) {
-                    context.cell_results.insert(name, CellResult::Done(*value));
+                    if let Some(ty) = param.ty {
+                        if value.type_name() != ty.to_string() {
+                            bail!(
+                                "param `{}` is a {}, found {} {}",
+                                name,
+                                ty,
+                                value.type_name(),
+                                value
+                            );
+                        }
+                    }
+                    context
+                        .cell_results
+                        .insert(name, CellResult::Done(value.clone()));
                 } else {
-                    bail!("param `{}` not found", name);
+                    match did_you_mean(name, params.keys().map(String::as_str)) {
+                        Some(x) => bail!("param `{}` not found, did you mean `{}`?", name, x),
+                        None => bail!("param `{}` not found", name),
+                    }
                 }
             }
+            Node::Directive(directive) => match (directive.name.as_str(), &directive.arguments[..])
+            {
+                ("eq-tolerance", [Number(x)]) => context.eq_tolerance = *x,
+                ("eq-tolerance", _) => bail!("@eq-tolerance expects 1 number"),
+                ("periods", [Number(x)]) if *x >= 1.0 && x.fract() == 0.0 => {
+                    context.periods = Some(*x as usize)
+                }
+                ("periods", _) => bail!("@periods expects 1 positive whole number"),
+                ("iterate", []) => context.iteration = Some((100, 0.001)),
+                ("iterate", [Number(max), Number(epsilon)])
+                    if *max >= 1.0 && max.fract() == 0.0 && *epsilon >= 0.0 =>
+                {
+                    context.iteration = Some((*max as usize, *epsilon))
+                }
+                ("iterate", _) => bail!("@iterate expects the max iterations and the epsilon"),
+                (x, _) => bail!("unknown directive `@{}`", x),
+            },
+            Node::Import(import) => bail!(
+                "`import \"{}\"` must be resolved before evaluation",
+                import.path
+            ),
+            Node::Assert(assert) => asserts.push(assert),
+        }
+    }
+    let mut results = run_cells(cell_names, &mut context)?;
+    if let Some((max_iterations, epsilon)) = context.iteration {
+        let mut iterations = 1;
+        while context.cyclic && !converged(&context.guesses, &context.iterates, epsilon) {
+            if iterations == max_iterations {
+                bail!(
+                    "circular references did not converge within {} iterations",
+                    max_iterations
+                );
+            }
+            context.guesses = std::mem::take(&mut context.iterates);
+            context.period_results.clear();
+            context.cyclic = false;
+            results = run_cells(cell_names, &mut context)?;
+            iterations += 1;
         }
     }
-    let mut results = vec![];
 
-    for cell_name in cell_names {
-        let cell = context.find_cell(cell_name)?;
+    for assert in asserts {
         context
             .call_stack
             .try_borrow_mut()?
-            .push(cell_name.to_string());
-
-        let result = match cell {
-            CellResult::Pending(x) => run_expr(x, &mut context)?,
-            CellResult::Done(x) => *x,
+            .push("assert".to_string());
+        let passed =
+            run_bool(&assert.expr, &mut context).map_err(|e| with_completed(e, &results))?;
+        context.call_stack.try_borrow_mut()?.pop();
+        if passed {
+            continue;
+        }
+        let mut message = match &assert.message {
+            Some(message) => format!("assertion `{}` failed: {}", assert.expr, message),
+            None => format!("assertion `{}` failed", assert.expr),
         };
-        results.push((cell_name.to_string(), result))
+        let params: Vec<_> = code
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Param(param) => Some(format!("{} = {}", param.name, params[&param.name])),
+                _ => None,
+            })
+            .collect();
+        if !params.is_empty() {
+            message.push_str(&format!(" (with {})", params.join(", ")));
+        }
+        match &options.assert_warnings {
+            Some(warnings) => warnings.push(message),
+            None => bail!(message),
+        }
     }
 
+    #[cfg(feature = "telemetry")]
+    crate::telemetry::emit(|| crate::telemetry::Event::Evaluated {
+        engine: "ast",
+        cells: cell_names.len(),
+        expressions: context.evaluated,
+        duration: started.elapsed(),
+    });
     Ok(results)
 }
 
@@ -178,23 +1230,25 @@ mod tests {
     use crate::scanner;
 
     #[track_caller]
-    fn test(code: &str, cell_name: &str) -> f64 {
-        let ast = parser::parse(scanner::scan(code).unwrap()).unwrap();
-        run(&ast, &[cell_name], &HashMap::new()).unwrap()[0].1
+    fn test(code: &str, cell_name: &str) -> Value {
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        run(&ast, &[cell_name], &HashMap::new()).unwrap()[0]
+            .1
+            .clone()
     }
 
     #[track_caller]
     fn test_expect_error(code: &str, cell_name: &str) {
-        let ast = parser::parse(scanner::scan(code).unwrap()).unwrap();
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
         if let Ok(x) = run(&ast, &[cell_name], &HashMap::new()) {
             panic!("expected error but got {:?}", x);
         }
     }
 
     #[track_caller]
-    fn test_with_param(code: &str, cell_name: &str, params: &Params) -> f64 {
-        let ast = parser::parse(scanner::scan(code).unwrap()).unwrap();
-        run(&ast, &[cell_name], params).unwrap()[0].1
+    fn test_with_param(code: &str, cell_name: &str, params: &Params) -> Value {
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        run(&ast, &[cell_name], params).unwrap()[0].1.clone()
     }
 
     #[test]
@@ -212,6 +1266,8 @@ mod tests {
 
     #[test]
     fn test_simple() {
+        assert_eq!(test("cell a: 5-1;", "a"), 4f64);
+        assert_eq!(test("cell a: 5 - -1;", "a"), 6f64);
         assert_eq!(
             test(
                 r#"
@@ -263,6 +1319,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_precedence() {
+        assert_eq!(test("cell a: 1 + 2 * 3;", "a"), 7f64);
+        assert_eq!(test("cell a: 1 - 2 - 3;", "a"), -4f64);
+        assert_eq!(test("cell a: 8 / 4 / 2;", "a"), 1f64);
+        assert_eq!(test("cell a: 2 * 3 % 4 + 1;", "a"), 3f64);
+        assert_eq!(test("cell a: (1 + 2) * 3;", "a"), 9f64);
+        assert_eq!(test("cell a: if 1 + 2 * 3 == 7 ? 1 : 0;", "a"), 1f64);
+        assert_eq!(test("cell b: 2; cell a: -b * 3 - -(b + 1);", "a"), -3f64);
+        assert_eq!(test("cell a: 2 * 2 ** 3 ** 2;", "a"), 1024f64);
+        assert_eq!(
+            test("cell b: 3; cell a: -b ** 2 + pow(2, 0.5) ** 2;", "a"),
+            -7f64
+        );
+        assert_eq!(test("cell b: 3; cell a: (-b) ** 2;", "a"), 9f64);
+    }
+
+    #[test]
+    fn test_eq_tolerance() {
+        assert_eq!(test("cell a: if 0.1 + 0.2 == 0.3 ? 1 : 0;", "a"), 0f64);
+        assert_eq!(
+            test(
+                "@eq-tolerance(1e-9) cell a: if 0.1 + 0.2 == 0.3 ? 1 : 0;",
+                "a"
+            ),
+            1f64
+        );
+        assert_eq!(
+            test("cell a: approx_eq(0.1 + 0.2, 0.3, 1e-9);", "a"),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            test("cell a: approx_eq(1, 2, 0.5);", "a"),
+            Value::Bool(false)
+        );
+        test_expect_error("@eq-tolerance() cell a: 1;", "a");
+        test_expect_error("@unknown(1) cell a: 1;", "a");
+    }
+
     macro_rules! test_with_param {
         ($code:expr, $cell_name:expr, { $(
             $key:expr => $value: expr,
@@ -270,7 +1365,7 @@ mod tests {
             {
                 let mut params = HashMap::new();
                 $(
-                    params.insert($key.to_owned(), $value);
+                    params.insert($key.to_owned(), Value::from($value));
                 )*
                 test_with_param($code, $cell_name, &params)
             }
@@ -310,8 +1405,579 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cancellation() {
+        let ast = parser::parse(scanner::scan_spanned("cell a: 1 + 2;").unwrap()).unwrap();
+        let options = RunOptions::default();
+        assert!(run_with_options(&ast, &["a"], &HashMap::new(), &options).is_ok());
+        options.cancellation.cancel();
+        assert!(run_with_options(&ast, &["a"], &HashMap::new(), &options).is_err());
+    }
+
+    #[test]
+    fn test_iterate() {
+        // overhead is allocated in proportion to the total cost, which
+        // includes the overhead
+        let code = r#"
+            @iterate(100, 1e-9)
+            cell direct: 100;
+            cell overhead: 0.2 * total;
+            cell total: direct + overhead;
+        "#;
+        assert!((test(code, "total").as_number().unwrap() - 125.0).abs() < 1e-6);
+
+        test_expect_error("@iterate(10, 0) cell a: a + 1;", "a");
+        test_expect_error("@iterate(10) cell a: 1;", "a");
+    }
+
+    #[test]
+    fn test_evaluation_depth() {
+        let run_deep = |code: String, cell: &'static str| {
+            std::thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(move || {
+                    let ast = parser::parse(scanner::scan_spanned(&code).unwrap()).unwrap();
+                    run(&ast, &[cell], &HashMap::new()).map_err(|e| e.to_string())
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        let periods = |n: usize| {
+            format!(
+                "@periods({}) cell b[t]: if t == 0 ? 1 : b[t-1] + 1; cell last: b[{}];",
+                n,
+                n - 1
+            )
+        };
+        assert_eq!(
+            run_deep(periods(1000), "last").unwrap(),
+            vec![("last".to_string(), Value::from(1000f64))]
+        );
+        assert_eq!(
+            run_deep(periods(2000), "last").unwrap_err(),
+            "evaluating `b[633]` nests more than 4096 levels deep through the cells it references"
+        );
+
+        let mut code = "cell c0: 1;".to_string();
+        for i in 1..5000 {
+            code += &format!("cell c{}: c{} + 1;", i, i - 1);
+        }
+        assert!(run_deep(code, "c4999")
+            .unwrap_err()
+            .starts_with("evaluating `c2950` nests more than 4096 levels deep"));
+    }
+
+    #[test]
+    fn test_time_index() {
+        let code = r#"
+            @periods(4)
+            param rate;
+            cell balance[t]: if t == 0 ? 100 : balance[t-1] * (1 + rate);
+            cell interest[t]: if t == 0 ? 0 : balance[t] - balance[t - 1];
+            cell total_interest: sum(interest);
+            cell last: balance[3];
+        "#;
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let params = HashMap::from([("rate".to_string(), Value::from(0.5))]);
+        assert_eq!(
+            run(&ast, &["balance", "total_interest", "last"], &params).unwrap(),
+            vec![
+                (
+                    "balance".to_string(),
+                    Value::List(vec![
+                        Value::from(100f64),
+                        Value::from(150f64),
+                        Value::from(225f64),
+                        Value::from(337.5)
+                    ])
+                ),
+                ("total_interest".to_string(), Value::from(237.5)),
+                ("last".to_string(), Value::from(337.5)),
+            ]
+        );
+
+        let code = "@periods(2) cell a[t]: a[t - 1]; cell b[t]: b[t];";
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        assert_eq!(
+            run(&ast, &["a"], &HashMap::new()).unwrap_err().to_string(),
+            "`a[-1]` is out of range, the model has 2 periods"
+        );
+        assert!(run(&ast, &["b"], &HashMap::new())
+            .unwrap_err()
+            .to_string()
+            .starts_with("cyclic dependency found"));
+
+        let ast = parser::parse(scanner::scan_spanned("cell a[t]: t;").unwrap()).unwrap();
+        assert!(run(&ast, &["a"], &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_assert() {
+        let ast = parser::parse(
+            scanner::scan_spanned(
+                "param price; cell total: price * 2; assert total >= 0 : \"negative total\";",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let params = HashMap::from([("price".to_string(), Value::from(-1f64))]);
+        assert_eq!(
+            run(&ast, &["total"], &params).unwrap_err().to_string(),
+            "assertion `total >= 0` failed: negative total (with price = -1)"
+        );
+        let warnings = WarningBuffer::new();
+        let options = RunOptions {
+            assert_warnings: Some(warnings.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            run_with_options(&ast, &["total"], &params, &options).unwrap(),
+            vec![("total".to_string(), Value::from(-2f64))]
+        );
+        assert_eq!(
+            warnings.take(),
+            vec!["assertion `total >= 0` failed: negative total (with price = -1)"]
+        );
+        let params = HashMap::from([("price".to_string(), Value::from(1f64))]);
+        assert!(run(&ast, &["total"], &params).is_ok());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let ast = parser::parse(scanner::scan_spanned("cell a: 1 + 2;").unwrap()).unwrap();
+        let options = RunOptions {
+            deadline: Some((Instant::now(), Duration::from_secs(60))),
+            ..Default::default()
+        };
+        assert!(run_with_options(&ast, &["a"], &HashMap::new(), &options).is_ok());
+        let options = RunOptions {
+            deadline: Some((Instant::now() - Duration::from_secs(1), Duration::ZERO)),
+            ..Default::default()
+        };
+        let error = run_with_options(&ast, &["a"], &HashMap::new(), &options).unwrap_err();
+        assert!(error.is::<TimedOut>());
+
+        // the cells queried before the timeout are kept, a param is
+        // done without evaluating anything
+        let ast = parser::parse(scanner::scan_spanned("param p; cell a: p + 1;").unwrap()).unwrap();
+        let params = HashMap::from([("p".to_string(), Value::from(1f64))]);
+        let error = run_with_options(&ast, &["p", "a"], &params, &options).unwrap_err();
+        assert_eq!(
+            error.downcast::<TimedOut>().unwrap().completed,
+            vec![("p".to_string(), Value::from(1f64))]
+        );
+    }
+
+    #[test]
+    fn test_tier_lookup() {
+        let ast = parser::parse(
+            scanner::scan_spanned("param usage; cell rate: tier_lookup(usage * 2, rates);")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut options = RunOptions::default();
+        options.tables.insert(
+            "rates".to_string(),
+            Table::new(vec![(0.0, 10.0), (100.0, 8.0), (1000.0, 5.0)]).unwrap(),
+        );
+        let run = |usage: f64| {
+            let params = HashMap::from([("usage".to_string(), Value::Number(usage))]);
+            run_with_options(&ast, &["rate"], &params, &options).map(|x| x[0].1.clone())
+        };
+        assert_eq!(run(10.0).unwrap(), 10.0);
+        assert_eq!(run(50.0).unwrap(), 8.0);
+        assert_eq!(run(500.0).unwrap(), 5.0);
+        assert!(run(-1.0).is_err());
+        options.tables.clear();
+        let params = HashMap::from([("usage".to_string(), Value::Number(1.0))]);
+        assert!(run_with_options(&ast, &["rate"], &params, &options).is_err());
+    }
+
+    #[test]
+    fn test_interp() {
+        assert_eq!(test("cell a: interp(5, 0, 10, 10, 30);", "a"), 20f64);
+        assert_eq!(test("cell a: interp(20, 0, 10, 10, 30);", "a"), 50f64);
+        test_expect_error("cell a: interp(1, 2, 3, 2, 4);", "a");
+        test_expect_error("cell a: interp(1, 2, 3);", "a");
+
+        let ast = parser::parse(
+            scanner::scan_spanned("param load; cell latency: interp(load, curve);").unwrap(),
+        )
+        .unwrap();
+        let mut options = RunOptions::default();
+        options.tables.insert(
+            "curve".to_string(),
+            Table::new(vec![(0.0, 5.0), (0.5, 10.0), (1.0, 100.0)]).unwrap(),
+        );
+        let params = HashMap::from([("load".to_string(), Value::Number(0.75))]);
+        let result = run_with_options(&ast, &["latency"], &params, &options).unwrap();
+        assert_eq!(result[0].1, 55f64);
+        options.tables.clear();
+        assert!(run_with_options(&ast, &["latency"], &params, &options).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let code = "param x; cell a: trace(x * 2) + 1; cell b: trace(a);";
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let params = HashMap::from([("x".to_string(), Value::from(3f64))]);
+        let trace = TraceBuffer::new();
+        let options = RunOptions {
+            trace: Some(trace.clone()),
+            ..Default::default()
+        };
+        let result = run_with_options(&ast, &["b"], &params, &options).unwrap();
+        assert_eq!(result[0].1, 7f64);
+        assert_eq!(
+            trace.take(),
+            vec![
+                TraceEntry {
+                    cell: "a".to_string(),
+                    value: Value::from(6f64),
+                    span: Some(9..34),
+                },
+                TraceEntry {
+                    cell: "b".to_string(),
+                    value: Value::from(7f64),
+                    span: Some(35..52),
+                },
+            ]
+        );
+        assert!(trace.take().is_empty());
+        // without a buffer the value just passes through
+        assert_eq!(test("cell a: trace(2) * 2;", "a"), 4f64);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let ast =
+            parser::parse(scanner::scan_spanned("cell a: b + b; cell b: 1 + 2 * 3;").unwrap())
+                .unwrap();
+        let options = RunOptions {
+            fuel: Some(100),
+            ..Default::default()
+        };
+        assert!(run_with_options(&ast, &["a"], &HashMap::new(), &options).is_ok());
+        let options = RunOptions {
+            fuel: Some(3),
+            ..Default::default()
+        };
+        let error = run_with_options(&ast, &["a"], &HashMap::new(), &options).unwrap_err();
+        assert!(error.is::<OutOfFuel>());
+        assert_eq!(
+            error.to_string(),
+            "evaluation ran out of fuel after 3 steps"
+        );
+    }
+
+    #[test]
+    fn test_host_function() {
+        let mut options = RunOptions::default();
+        options
+            .register(
+                "region_price",
+                HostFunction::new(2, |args| args[0] * args[1]),
+            )
+            .unwrap();
+        for builtin in ["round", "int"] {
+            assert_eq!(
+                options
+                    .register(builtin, HostFunction::new(1, |args| args[0]))
+                    .unwrap_err()
+                    .to_string(),
+                format!("`{}` is a builtin and can't be registered", builtin)
+            );
+        }
+        let run_code = |code: &str| {
+            let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+            run_with_options(&ast, &["a"], &HashMap::new(), &options)
+        };
+        assert_eq!(
+            run_code("cell a: region_price(3, 4) + 1;").unwrap()[0].1,
+            Value::from(13f64)
+        );
+        assert!(run_code("cell a: region_price(3);").is_err());
+        assert_eq!(
+            run_code("cell a: region_prise(3, 4);")
+                .unwrap_err()
+                .to_string(),
+            "undefined function region_prise, did you mean `region_price`?"
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        test_expect_error(r#"cell a: env("CELL_SCRIPT_TEST_ENV", 1);"#, "a");
+    }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(test("cell a: if 1 > 0 && 2 > 1 ? 1 : 0;", "a"), 1f64);
+        assert_eq!(test("cell a: if 1 > 2 || 2 > 1 ? 1 : 0;", "a"), 1f64);
+        assert_eq!(test("cell a: if !(1 > 2) ? 1 : 0;", "a"), 1f64);
+        assert_eq!(test("cell a: 1 < 2 && !false;", "a"), Value::Bool(true));
+        assert_eq!(test("cell a: true == false;", "a"), Value::Bool(false));
+        assert_eq!(
+            test("cell a: 1 + 1 == 2 || 1 / 0 > 0;", "a"),
+            Value::Bool(true)
+        );
+        // short-circuit: `b` is never evaluated
+        assert_eq!(
+            test("cell a: false && b; cell b: b;", "a"),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            test("cell a: true || b; cell b: b;", "a"),
+            Value::Bool(true)
+        );
+        assert_eq!(test("cell a: 1 + 1 != 2;", "a"), Value::Bool(false));
+        assert_eq!(test(r#"cell a: "aws" != "gcp";"#, "a"), Value::Bool(true));
+        assert_eq!(
+            test("@eq-tolerance(0.1) cell a: 1 != 1.05;", "a"),
+            Value::Bool(false)
+        );
+        test_expect_error("cell a: if 1 ? 1 : 0;", "a");
+        test_expect_error("cell a: true + 1;", "a");
+        test_expect_error("cell a: true > false;", "a");
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(test(r#"cell a: "gcp";"#, "a"), Value::from("gcp"));
+        assert_eq!(
+            test(r#"output cell a: "é" == "é";"#, "a"),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            test_with_param!(
+                r#"
+            param provider;
+            cell cost: if provider == "gcp" ? 10 : 20;
+            "#,
+                "cost",
+                {
+                    "provider" => "gcp",
+                }
+            ),
+            10f64
+        );
+        assert_eq!(
+            test_with_param!(
+                r#"
+            param provider;
+            cell cost: if provider == "gcp" ? 10 : 20;
+            "#,
+                "cost",
+                {
+                    "provider" => "aws",
+                }
+            ),
+            20f64
+        );
+        test_expect_error(r#"cell a: "gcp" + 1;"#, "a");
+        test_expect_error(r#"cell a: if "gcp" == 1 ? 1 : 2;"#, "a");
+        test_expect_error(r#"cell a: if "gcp" > "aws" ? 1 : 2;"#, "a");
+
+        let code = r#"
+            param provider: string;
+            cell cost: if provider == "gcp" ? 10 : 20;
+        "#;
+        assert_eq!(
+            test_with_param!(code, "cost", { "provider" => "gcp", }),
+            10f64
+        );
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let params = HashMap::from([("provider".to_string(), Value::from(1f64))]);
+        assert_eq!(
+            run(&ast, &["cost"], &params).unwrap_err().to_string(),
+            "param `provider` is a string, found number 1"
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let code = r#"
+        cell tiers: [0.10, 0.08, 0.05];
+        cell nested: [[1, 2], tiers];
+        cell a: tiers[1] * 100 + nested[0][1];
+        cell b: tiers == [0.1, 0.08, 0.05];
+        "#;
+        assert_eq!(test(code, "a"), 10f64);
+        assert_eq!(test(code, "b"), Value::Bool(true));
+        assert_eq!(
+            test(code, "nested"),
+            Value::List(vec![
+                Value::List(vec![1f64.into(), 2f64.into()]),
+                Value::List(vec![0.1.into(), 0.08.into(), 0.05.into()]),
+            ])
+        );
+        test_expect_error("cell a: [1, 2][2];", "a");
+        test_expect_error("cell a: [1, 2][0.5];", "a");
+        test_expect_error("cell a: 1[0];", "a");
+    }
+
+    #[test]
+    fn test_rounding() {
+        let cases = [
+            ("round(2.5)", 3.0),
+            ("round(-2.5)", -3.0),
+            ("round(2.4)", 2.0),
+            ("floor(-2.5)", -3.0),
+            ("ceil(-2.5)", -2.0),
+            ("trunc(-2.5)", -2.0),
+            ("trunc(2.5)", 2.0),
+            ("abs(-2.5)", 2.5),
+            ("sign(-2.5)", -1.0),
+            ("sign(0)", 0.0),
+            ("sign(2.5)", 1.0),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(
+                test(&format!("cell a: {};", expr), "a"),
+                expected,
+                "{}",
+                expr
+            );
+        }
+        test_expect_error("cell a: int(2.5);", "a");
+        test_expect_error("cell a: round(1, 2);", "a");
+    }
+
+    #[test]
+    fn test_distributions() {
+        let within = |code: &str, lo: f64, hi: f64| {
+            for _ in 0..100 {
+                let x = test(code, "a").as_number().unwrap();
+                assert!(lo <= x && x <= hi, "{} = {}", code, x);
+            }
+        };
+        within("cell a: rand();", 0.0, 1.0);
+        within("cell a: rand(-2, 3);", -2.0, 3.0);
+        within("cell a: uniform(5, 5);", 5.0, 5.0);
+        within("cell a: triangular(1, 2, 4);", 1.0, 4.0);
+        within("cell a: lognormal(0, 1);", 0.0, f64::INFINITY);
+        assert_eq!(test("cell a: normal(3, 0);", "a"), 3f64);
+
+        test_expect_error("cell a: rand(3, 2);", "a");
+        test_expect_error("cell a: uniform(1);", "a");
+        test_expect_error("cell a: normal(0, -1);", "a");
+        test_expect_error("cell a: triangular(1, 5, 4);", "a");
+    }
+
+    #[test]
+    fn test_seed() {
+        let ast = parser::parse(
+            scanner::scan_spanned("cell a: [rand(), normal(0, 1), triangular(0, 1, 2)];").unwrap(),
+        )
+        .unwrap();
+        let run_seeded = |options: &RunOptions| {
+            run_with_options(&ast, &["a"], &HashMap::new(), options).unwrap()[0]
+                .1
+                .clone()
+        };
+        let options = RunOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(run_seeded(&options), run_seeded(&options));
+        assert_eq!(
+            run_seeded(&options.stream(1)),
+            run_seeded(&options.stream(1))
+        );
+        assert_ne!(run_seeded(&options), run_seeded(&options.stream(1)));
+        assert_ne!(
+            run_seeded(&options.stream(1)),
+            run_seeded(&options.stream(2))
+        );
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        let ast = parser::parse(
+            scanner::scan_spanned(
+                "cell a: 0.1 + 0.1 + 0.1 + 0.1 + 0.1 + 0.1 + 0.1 + 0.1 + 0.1 + 0.1;
+                 cell b: 1e16 + 1 + 1 - 1e16;
+                 cell c: sum_exact([1e100, 1, -1e100]);",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let run_abc = |options: &RunOptions| -> Vec<Value> {
+            run_with_options(&ast, &["a", "b", "c"], &HashMap::new(), options)
+                .unwrap()
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect()
+        };
+        let naive = run_abc(&RunOptions::default());
+        assert_ne!(naive[0], 1f64);
+        assert_eq!(naive[1], 0f64);
+        assert_eq!(naive[2], 1f64);
+        let compensated = run_abc(&RunOptions {
+            compensated_sum: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            compensated,
+            vec![Value::from(1f64), 2f64.into(), 1f64.into()]
+        );
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let code = r#"
+        cell costs: [3, 1.5, 4.5];
+        cell total: sum(costs);
+        cell average: avg(costs);
+        cell range: max(costs) - min(costs);
+        "#;
+        assert_eq!(test(code, "total"), 9f64);
+        assert_eq!(test(code, "average"), 3f64);
+        assert_eq!(test(code, "range"), 3f64);
+        assert_eq!(test("cell a: sum([]);", "a"), 0f64);
+        assert_eq!(test("cell a: 1 / sum([]);", "a"), f64::INFINITY);
+        assert_eq!(test("cell a: 1 / sum_exact([]);", "a"), f64::INFINITY);
+        test_expect_error("cell a: avg([]);", "a");
+        test_expect_error("cell a: max([]);", "a");
+        test_expect_error("cell a: sum(1);", "a");
+        test_expect_error(r#"cell a: sum([1, "2"]);"#, "a");
+
+        assert_eq!(test("cell a: min(3, 1, 2) + max(1, 5);", "a"), 6f64);
+        assert_eq!(test("cell a: clamp(5, 0, 1) + clamp(-5, 0, 1);", "a"), 1f64);
+        assert_eq!(test("cell a: clamp(0.5, 0, 1);", "a"), 0.5f64);
+        test_expect_error("cell a: min(1, [2]);", "a");
+        test_expect_error("cell a: clamp(1, 2, 0);", "a");
+        test_expect_error("cell a: clamp(1, 2);", "a");
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let error = |code: &str, params: &Params| {
+            let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+            run(&ast, &["a"], params).unwrap_err().to_string()
+        };
+        assert_eq!(
+            error("cell cpu_cost: 1; cell a: cpu_cots * 2;", &HashMap::new()),
+            "`cpu_cots` is not defined, did you mean `cpu_cost`?"
+        );
+        assert_eq!(
+            error("cell a: trunk(1.5);", &HashMap::new()),
+            "undefined function trunk, did you mean `trunc`?"
+        );
+        assert_eq!(
+            error(
+                "param provider; cell a: provider;",
+                &HashMap::from([("providr".to_string(), Value::from("gcp"))])
+            ),
+            "param `provider` not found, did you mean `providr`?"
+        );
+        assert_eq!(error("cell a: b;", &HashMap::new()), "`b` is not defined");
+    }
+
     #[test]
     fn test_cyclic() {
+        test_expect_error("cell a: 1 + b; cell b: 2 * a;", "a");
         test_expect_error(
             r#"
         cell a: b;
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::ast_interpreter::Value;
+
+/*
+
+$ cell-script app.cell -p usage=10 -q total -f json > baseline.json
+# ... edit app.cell ...
+$ cell-script app.cell -p usage=10 -q total -f json --baseline baseline.json
+[
+  {
+    "input": { "usage": 10.0 },
+    "output": { "total": 105.0 },
+    "baseline": { "total": { "value": 100.0, "delta": 5.0 } },
+    ...
+  }
+]
+
+*/
+
+/// The rows of an earlier json output, compared with the rows of the
+/// current run that have the same input
+#[derive(Debug)]
+pub struct Baseline {
+    rows: Vec<Row>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Row {
+    input: IndexMap<String, Value>,
+    output: IndexMap<String, Value>,
+}
+
+/// A cell's value in the baseline, and how far the current value moved
+/// from it when both are numbers
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct Comparison {
+    pub value: Value,
+    pub delta: Option<f64>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            anyhow::Error::new(e).context(format!("cannot read baseline `{}`", path.display()))
+        })?;
+        Self::parse(&content)
+            .map_err(|e| e.context(format!("invalid baseline `{}`", path.display())))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            rows: serde_json::from_str(content)?,
+        })
+    }
+
+    /// Comparisons for the cells of `output` which the baseline row with the
+    /// same `input` has. Empty when there is no such row.
+    pub fn compare(
+        &self,
+        input: &IndexMap<String, Value>,
+        output: &IndexMap<String, Value>,
+    ) -> IndexMap<String, Comparison> {
+        let row = match self.rows.iter().find(|row| row.input == *input) {
+            Some(row) => row,
+            None => return IndexMap::new(),
+        };
+        output
+            .iter()
+            .filter_map(|(cell, value)| {
+                let before = row.output.get(cell)?;
+                let delta = match (value, before) {
+                    (Value::Number(after), Value::Number(before)) => Some(after - before),
+                    _ => None,
+                };
+                Some((
+                    cell.clone(),
+                    Comparison {
+                        value: before.clone(),
+                        delta,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let baseline = Baseline::parse(
+            r#"[
+                {"input": {"a": 1.0, "b": "x"}, "output": {"total": 10.0, "name": "old"}, "seed": 1},
+                {"input": {"a": 2.0, "b": "x"}, "output": {"total": 20.0}, "seed": 1}
+            ]"#,
+        )
+        .unwrap();
+        let input = IndexMap::from([
+            ("b".to_string(), Value::from("x")),
+            ("a".to_string(), Value::from(1f64)),
+        ]);
+        let output = IndexMap::from([
+            ("total".to_string(), Value::from(12.5)),
+            ("name".to_string(), Value::from("new")),
+            ("added".to_string(), Value::from(1f64)),
+        ]);
+        let compared = baseline.compare(&input, &output);
+        assert_eq!(
+            compared,
+            IndexMap::from([
+                (
+                    "total".to_string(),
+                    Comparison {
+                        value: Value::from(10f64),
+                        delta: Some(2.5)
+                    }
+                ),
+                (
+                    "name".to_string(),
+                    Comparison {
+                        value: Value::from("old"),
+                        delta: None
+                    }
+                ),
+            ])
+        );
+
+        let input = IndexMap::from([("a".to_string(), Value::from(3f64))]);
+        assert!(baseline.compare(&input, &output).is_empty());
+        assert!(Baseline::parse("{}").is_err());
+
+        // the cause is kept under the path
+        let error = format!(
+            "{:#}",
+            Baseline::load(Path::new("missing.json")).unwrap_err()
+        );
+        assert!(
+            error.starts_with("cannot read baseline `missing.json`: "),
+            "{}",
+            error
+        );
+    }
+}
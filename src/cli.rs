@@ -1,24 +1,214 @@
-use crate::{ast_interpreter, parser::parse, scanner::scan};
+use crate::{
+    ast_interpreter::{self, TimedOut, TraceBuffer, Value, WarningBuffer},
+    baseline::{Baseline, Comparison},
+    composite::CompositeModel,
+    diagnostic::{did_you_mean, Diagnostic},
+    dump,
+    hash::hash,
+    import, literate, mutate,
+    parser::{parse, Cell, Node, Param, ParamType, AST},
+    query,
+    report::{self, ReportFormat},
+    scanner::scan_spanned,
+    sdiff::sdiff,
+    sema::{self, Limits},
+    simplify,
+    table::{CsvDialect, Table},
+    workspace::{self, Workspace},
+};
 use anyhow::bail;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use indexmap::IndexMap;
 use itertools::Itertools;
 use rayon::prelude::*;
 use serde_json;
-use std::{collections::HashMap, fmt::Display, path::PathBuf, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    code_path: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(required = true)]
+    code_path: Option<PathBuf>,
 
     #[clap(short, long, default_value_t = OutputFormat::Text)]
     format: OutputFormat,
 
+    /// comma separated cells to compute, the `output` cells of the model when missing. `cost_*` and `/^cost_/` select every matching cell
     #[clap(short, long)]
-    query: String,
+    query: Option<String>,
 
     #[clap(short, long)]
     param: Vec<String>,
+
+    /// read params from a csv file with one `name,value,value...` row per param
+    #[clap(long)]
+    params_file: Vec<PathBuf>,
+
+    /// `comma` (`1.5,2`) or `semicolon` (`1,5;2`) for --params-file and --table files
+    #[clap(long, default_value = "comma")]
+    csv_dialect: CsvDialect,
+
+    /// bind the cells of another model as `<alias>.<cell>` params. usage --link "other.cell as alias"
+    #[clap(short, long)]
+    link: Vec<String>,
+
+    /// rename a caller-side param to a model param. usage --map "price=unit_price"
+    #[clap(short, long)]
+    map: Vec<String>,
+
+    /// load a `threshold,value` csv for tier_lookup() or interp(). usage --table "rates=rates.csv"
+    #[clap(short, long)]
+    table: Vec<String>,
+
+    /// seed for rand() and the distribution builtins. a random seed is used, and recorded in the json output, when missing
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// evaluate chains of `+` and `-` with compensated (Kahan) summation
+    #[clap(long)]
+    compensated_sum: bool,
+
+    /// the json output of an earlier run, rows with the same input get the earlier value and delta of each cell
+    #[clap(long)]
+    baseline: Option<PathBuf>,
+
+    /// print failed `assert`s as warnings instead of failing the permutation
+    #[clap(long)]
+    assert_warnings: bool,
+
+    /// give up on a permutation after this long (e.g. `50ms`, `2s`) and report it as timed out,
+    /// with the queried cells that completed
+    #[clap(long, parse(try_from_str = parse_duration))]
+    permutation_timeout: Option<Duration>,
+
+    /// fail a permutation that evaluates more than this many expressions, to bound untrusted models
+    #[clap(long)]
+    fuel: Option<u64>,
+
+    /// validate the model, params and query and print what would run, without evaluating
+    #[clap(long)]
+    dry_run: bool,
+
+    /// print the values passed to `trace(x)` to stderr
+    #[clap(long)]
+    trace: bool,
+
+    /// print the time spent scanning, parsing, checking, optimizing and evaluating to stderr
+    #[clap(long)]
+    timings: bool,
+
+    /// warn about cells whose expressions nest deeper than this
+    #[clap(long, default_value_t = Limits::default().max_depth)]
+    max_depth: usize,
+
+    /// warn about cells that reference more cells and params than this
+    #[clap(long, default_value_t = Limits::default().max_dependencies)]
+    max_dependencies: usize,
+
+    /// refuse to run a model with warnings, e.g. unused params
+    #[clap(long)]
+    deny_warnings: bool,
+
+    /// print the `tokens` of the model, its `ast` as json, or the `simplified` model that would be evaluated for the query, instead of running it
+    #[clap(long)]
+    emit: Option<Emit>,
+
+    /// write --emit to this file instead of stdout
+    #[clap(long, requires = "emit")]
+    emit_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// print a hash of the model that ignores comments and formatting
+    Hash { code_path: PathBuf },
+    /// print the AST of a model and its imports as JSON
+    Dump {
+        code_path: PathBuf,
+
+        /// only `json`
+        #[clap(short, long, default_value = "json")]
+        format: String,
+
+        /// write an older version of the format
+        #[clap(long, default_value_t = dump::FORMAT_VERSION)]
+        format_version: u32,
+    },
+    /// list the params, cells and directives that were added, removed or changed between two models
+    Sdiff { before: PathBuf, after: PathBuf },
+    /// report the diagnostics of a model and its imports without running it
+    Check {
+        code_path: PathBuf,
+
+        /// `text`, `json` or `sarif`
+        #[clap(short, long, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        #[clap(long, default_value_t = Limits::default().max_depth)]
+        max_depth: usize,
+
+        #[clap(long, default_value_t = Limits::default().max_dependencies)]
+        max_dependencies: usize,
+
+        /// fail on warnings too
+        #[clap(long)]
+        deny_warnings: bool,
+    },
+    /// check how many small changes to the model's cells its `assert`s catch
+    Mutate {
+        code_path: PathBuf,
+
+        /// the values to run the assertions with, every permutation is tried
+        #[clap(short, long)]
+        param: Vec<String>,
+    },
+    /// write a `.cell.md` model as an HTML page, with the values of its cells after each code block
+    Render {
+        code_path: PathBuf,
+
+        /// one value per param
+        #[clap(short, long)]
+        param: Vec<String>,
+
+        /// where to write the page, instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// run a model listed in the workspace manifest with its default query and params
+    Run {
+        #[clap(short, long)]
+        model: String,
+
+        /// path of the workspace manifest
+        #[clap(short, long, default_value = workspace::MANIFEST)]
+        workspace: PathBuf,
+
+        #[clap(short, long, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// replaces the default query of the model
+        #[clap(short, long)]
+        query: Option<String>,
+
+        /// replaces the default values of a param
+        #[clap(short, long)]
+        param: Vec<String>,
+
+        #[clap(long)]
+        seed: Option<u64>,
+    },
 }
 
 #[derive(Debug)]
@@ -45,70 +235,864 @@ impl FromStr for OutputFormat {
     }
 }
 
+/// What `--emit` prints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Simplified,
+}
+
+impl FromStr for Emit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tokens" => Ok(Self::Tokens),
+            "ast" => Ok(Self::Ast),
+            "simplified" => Ok(Self::Simplified),
+            _ => bail!(
+                "unrecognized emit `{}`, expected `tokens`, `ast` or `simplified`",
+                s
+            ),
+        }
+    }
+}
+
+/// One evaluated permutation. `input` keeps the `--param` order and `output`
+/// keeps the `--query` order; rows keep the order of the permutations.
+/// `seed` is the `--seed` that reproduces the whole run. `docs` has the
+/// `##` comments of the params and cells in the row. A row that ran past
+/// `--permutation-timeout` is `partial`, its output has only the queried
+/// cells that completed. `baseline` compares the output with the
+/// `--baseline` row of the same input.
 #[derive(Debug, serde::Serialize)]
 struct Output {
-    input: HashMap<String, f64>,
-    output: HashMap<String, f64>,
+    input: IndexMap<String, Value>,
+    output: IndexMap<String, Value>,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    baseline: IndexMap<String, Comparison>,
+    seed: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    timed_out: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    docs: IndexMap<String, String>,
+}
+
+/// Where the time of a run went, for `--timings`. Scanning and parsing
+/// include the imported and linked files.
+#[derive(Debug, Default)]
+struct Timings {
+    scan: Duration,
+    parse: Duration,
+    sema: Duration,
+    /// pruning, inlining and simplifying
+    optimization: Duration,
+    /// wall time of all the permutations
+    evaluation: Duration,
+    /// the time of the permutations added up
+    permutation: Duration,
+    permutations: usize,
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "scan: {:?}", self.scan)?;
+        writeln!(f, "parse: {:?}", self.parse)?;
+        writeln!(f, "sema: {:?}", self.sema)?;
+        writeln!(f, "optimization: {:?}", self.optimization)?;
+        write!(f, "evaluation: {:?}", self.evaluation)?;
+        if self.permutations > 0 {
+            write!(
+                f,
+                " ({} permutations, {:?} each on average)",
+                self.permutations,
+                self.permutation / self.permutations as u32
+            )?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Diagnostics are colored when they are printed to a terminal, unless
+/// `NO_COLOR` is set
+fn color_enabled() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Loads a model, with its imports resolved
+fn load_ast(path: &Path) -> Result<AST, anyhow::Error> {
+    Ok(import::load(path, &mut parse_source)?.0)
+}
+
+fn parse_source(path: &Path, content: &str) -> Result<AST, anyhow::Error> {
+    parse_source_timed(path, content, &mut Timings::default())
+}
+
+fn parse_source_timed(
+    path: &Path,
+    content: &str,
+    timings: &mut Timings,
+) -> Result<AST, anyhow::Error> {
+    let started = Instant::now();
+    let tokens = scan_spanned(content);
+    timings.scan += started.elapsed();
+    let started = Instant::now();
+    let ast = tokens.and_then(parse);
+    timings.parse += started.elapsed();
+    ast.map_err(|e| match e.downcast_ref::<Diagnostic>() {
+        Some(diagnostic) => anyhow::Error::msg(diagnostic.render_with(
+            content,
+            &path.display().to_string(),
+            color_enabled(),
+        )),
+        None => e,
+    })
+}
+
+/// Like `load_ast`, but also runs the static checks. Warnings are printed to
+/// stderr, errors (and with `deny_warnings` warnings too) fail the load.
+fn load_checked_ast(
+    path: &Path,
+    limits: &Limits,
+    deny_warnings: bool,
+    timings: &mut Timings,
+) -> Result<AST, anyhow::Error> {
+    let (ast, source_map) = import::load(path, &mut |path, content| {
+        parse_source_timed(path, content, timings)
+    })?;
+    let started = Instant::now();
+    let diagnostics = sema::check(&ast, limits);
+    timings.sema += started.elapsed();
+    let mut errors = vec![];
+    for diagnostic in diagnostics {
+        let rendered = source_map.render(&diagnostic, color_enabled());
+        if diagnostic.is_error() || deny_warnings {
+            errors.push(rendered);
+        } else {
+            eprintln!("{}", rendered);
+        }
+    }
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(ast)
+}
+
+/// Prints every diagnostic of the model in `format`, failing if any of them
+/// is an error, or a warning with `deny_warnings`. A parse error is reported
+/// like the other diagnostics, but stops the check.
+fn check(
+    path: &Path,
+    format: ReportFormat,
+    limits: &Limits,
+    deny_warnings: bool,
+) -> Result<(), anyhow::Error> {
+    let mut failed = None;
+    let loaded = import::load(path, &mut |path, content| {
+        scan_spanned(content).and_then(parse).inspect_err(|_| {
+            failed = Some((path.to_path_buf(), content.to_string()));
+        })
+    });
+    let (diagnostics, source_map) = match loaded {
+        Ok((ast, source_map)) => (sema::check(&ast, limits), source_map),
+        Err(e) => match (e.downcast_ref::<Diagnostic>(), failed) {
+            (Some(diagnostic), Some((path, content))) => {
+                let mut source_map = import::SourceMap::default();
+                source_map.add(path, content);
+                (vec![diagnostic.clone()], source_map)
+            }
+            _ => return Err(e),
+        },
+    };
+
+    let report = report::render(&diagnostics, &source_map, format, color_enabled());
+    if !report.is_empty() {
+        println!("{}", report);
+    }
+    let errors = diagnostics.iter().filter(|x| x.is_error()).count();
+    if errors > 0 {
+        bail!("found {} error(s)", errors);
+    }
+    if deny_warnings && !diagnostics.is_empty() {
+        bail!(
+            "found {} warning(s), denied by --deny-warnings",
+            diagnostics.len()
+        );
+    }
+    Ok(())
 }
 
 pub fn run() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(Command::Hash { code_path }) => {
+            println!("{}", hash(&load_ast(&code_path)?));
+            Ok(())
+        }
+        Some(Command::Dump {
+            code_path,
+            format,
+            format_version,
+        }) => {
+            if format != "json" {
+                bail!(
+                    "unrecognized dump format `{}`, only `json` is supported",
+                    format
+                );
+            }
+            let dumped = dump::dump(&load_ast(&code_path)?, format_version)?;
+            println!("{}", serde_json::to_string_pretty(&dumped)?);
+            Ok(())
+        }
+        Some(Command::Sdiff { before, after }) => {
+            for change in sdiff(&load_ast(&before)?, &load_ast(&after)?) {
+                println!("{}", change);
+            }
+            Ok(())
+        }
+        Some(Command::Check {
+            code_path,
+            format,
+            max_depth,
+            max_dependencies,
+            deny_warnings,
+        }) => {
+            let limits = Limits {
+                max_depth,
+                max_dependencies,
+            };
+            check(&code_path, format, &limits, deny_warnings)
+        }
+        Some(Command::Mutate { code_path, param }) => {
+            let mut ast = load_ast(&code_path)?;
+            simplify::resolve_env(&mut ast)?;
+            let types = param_types([&ast]);
+            let mut names = vec![];
+            let mut values = vec![];
+            for param in &param {
+                let (name, param_values) = parse_param(param)?;
+                let ty = types.get(name.as_str()).copied();
+                values.push(typed_values(&param_values, ty, CsvDialect::default()));
+                names.push(name);
+            }
+            let permutations: Vec<_> = if values.is_empty() {
+                vec![HashMap::new()]
+            } else {
+                values
+                    .into_iter()
+                    .multi_cartesian_product()
+                    .map(|permutation| names.iter().cloned().zip(permutation).collect())
+                    .collect()
+            };
+            let report = mutate::run(&ast, &permutations)?;
+            println!(
+                "{} mutants, {} killed",
+                report.mutants,
+                report.mutants - report.survived.len()
+            );
+            for (cell, description) in report.survived {
+                println!("survived: cell `{}`: {}", cell, description);
+            }
+            Ok(())
+        }
+        Some(Command::Render {
+            code_path,
+            param,
+            output,
+        }) => {
+            let html = render(&code_path, &param)?;
+            match output {
+                Some(output) => std::fs::write(output, html)?,
+                None => print!("{}", html),
+            }
+            Ok(())
+        }
+        Some(Command::Run {
+            model,
+            workspace,
+            format,
+            query,
+            param,
+            seed,
+        }) => {
+            let args = workspace_args(&workspace, &model, format, query, param)?;
+            evaluate(Args { seed, ..args })
+        }
+        None => evaluate(args),
+    }
+}
+
+/// Evaluates every cell of a literate model and renders it as HTML
+fn render(path: &Path, param: &[String]) -> Result<String, anyhow::Error> {
+    if !literate::is_literate(path) {
+        bail!(
+            "`{}` is not a literate model, e.g. `model.cell.md`",
+            path.display()
+        );
+    }
+    let mut ast = load_ast(path)?;
+    simplify::resolve_env(&mut ast)?;
+    let types = param_types([&ast]);
+    let mut params = HashMap::new();
+    for param in param {
+        let (name, values) = parse_param(param)?;
+        let ty = types.get(name.as_str()).copied();
+        match &typed_values(&values, ty, CsvDialect::default())[..] {
+            [value] => params.insert(name, value.clone()),
+            _ => bail!("`render` takes one value for param `{}`", name),
+        };
+    }
+    let cells: Vec<_> = ast
+        .nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Cell(cell) if !sema::is_private(&cell.name) => Some(cell.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let results = ast_interpreter::run(&ast, &cells, &params)?
+        .into_iter()
+        .collect();
+    let markdown = std::fs::read_to_string(path)?;
+    let title = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(literate::render(&title, &markdown, &results))
+}
+
+/// The arguments `evaluate` would get if the model's entry in the workspace
+/// manifest was spelled out on the command line.
+fn workspace_args(
+    manifest: &Path,
+    model_name: &str,
+    format: OutputFormat,
+    query: Option<String>,
+    param: Vec<String>,
+) -> Result<Args, anyhow::Error> {
+    let workspace = Workspace::load(manifest)?;
+    let model = workspace.model(model_name)?;
+
+    // params given on the command line replace the defaults of the same name
+    let overridden: HashSet<_> = param
+        .iter()
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, _)| name)
+        .collect();
+    let mut params: Vec<_> = model
+        .params
+        .iter()
+        .filter(
+            |param| !matches!(param.split_once('='), Some((name, _)) if overridden.contains(name)),
+        )
+        .cloned()
+        .collect();
+    params.extend(param.iter().cloned());
+
+    Ok(Args {
+        command: None,
+        code_path: Some(workspace.resolve(&model.path)),
+        format,
+        query: query.or_else(|| model.query.clone()),
+        param: params,
+        link: model
+            .links
+            .iter()
+            .map(|(alias, path)| format!("{} as {}", workspace.resolve(path).display(), alias))
+            .collect(),
+        map: vec![],
+        seed: None,
+        params_file: vec![],
+        csv_dialect: CsvDialect::default(),
+        compensated_sum: false,
+        assert_warnings: false,
+        baseline: None,
+        permutation_timeout: None,
+        fuel: None,
+        dry_run: false,
+        trace: false,
+        timings: false,
+        max_depth: Limits::default().max_depth,
+        max_dependencies: Limits::default().max_dependencies,
+        deny_warnings: false,
+        emit: None,
+        emit_file: None,
+        table: workspace
+            .tables
+            .iter()
+            .map(|(name, path)| format!("{}={}", name, workspace.resolve(path).display()))
+            .collect(),
+    })
+}
+
+/// Warnings for a params file that drifted from the model: params the
+/// model doesn't declare, and declared params that aren't `given` at all
+fn stale_params(file_params: &[&str], declared: &[&str], given: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for name in file_params {
+        if !declared.contains(name) {
+            let diagnostic =
+                Diagnostic::warning(format!("param `{}` is not declared by the model", name));
+            diagnostics.push(match did_you_mean(name, declared.iter().copied()) {
+                Some(x) => diagnostic.with_hint(format!("did you mean `{}`?", x)),
+                None => diagnostic.with_hint("remove it from the file"),
+            });
+        }
+    }
+    for name in declared {
+        if !given.iter().any(|x| x == name) {
+            diagnostics.push(Diagnostic::warning(format!(
+                "param `{}` of the model is not set",
+                name
+            )));
+        }
+    }
+    diagnostics
+}
+
+/// `--param "name=1,2,3"`, the values are typed by [`typed_values`] once
+/// the model is known
+fn parse_param(param: &str) -> Result<(String, Vec<String>), anyhow::Error> {
+    match param.split_once('=') {
+        Some((name, values)) => {
+            let values = values.split(',').map(str::to_string).collect();
+            Ok((name.to_string(), values))
+        }
+        None => bail!("invalid param. usage --param \"name=1\""),
+    }
+}
+
+/// `<number><unit>` with `ms`, `s` or `m` as the unit, e.g. `50ms`
+fn parse_duration(value: &str) -> Result<Duration, anyhow::Error> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = match number.parse() {
+        Ok(x) => x,
+        Err(_) => bail!("invalid duration `{}`. usage `50ms`, `2s` or `1m`", value),
+    };
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => bail!("invalid duration `{}`. usage `50ms`, `2s` or `1m`", value),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// The declared type of every typed param of the models
+fn param_types<'a>(asts: impl IntoIterator<Item = &'a AST>) -> HashMap<&'a str, ParamType> {
+    asts.into_iter()
+        .flat_map(|ast| &ast.nodes)
+        .filter_map(|node| match node {
+            Node::Param(Param {
+                name, ty: Some(ty), ..
+            }) => Some((name.as_str(), *ty)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The values of a param of type `ty`. A `string` param takes the text as
+/// is, e.g. a zip code like `01234`
+fn typed_values(values: &[String], ty: Option<ParamType>, dialect: CsvDialect) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| match ty {
+            Some(ParamType::String) => Value::String(value.clone()),
+            _ => parse_value(value, dialect),
+        })
+        .collect()
+}
+
+/// Anything that isn't a number or a bool is passed as a string
+fn parse_value(value: &str, dialect: CsvDialect) -> Value {
+    match (dialect.parse_number(value), value.parse::<bool>()) {
+        (Some(x), _) => Value::Number(x),
+        (_, Ok(x)) => Value::Bool(x),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+/// Writes the `--emit` output to `path`, or stdout
+fn emit(output: &str, path: Option<&Path>) -> Result<(), anyhow::Error> {
+    match path {
+        Some(path) => std::fs::write(path, output)?,
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+/// `name,value,value...` rows, each one equivalent to a `--param`. Empty
+/// lines and `#` comments are skipped.
+fn parse_params_csv(
+    content: &str,
+    dialect: CsvDialect,
+) -> Result<Vec<(String, Vec<String>)>, anyhow::Error> {
+    let mut params = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match dialect.split(line)[..] {
+            [name, ref values @ ..] if !name.is_empty() && !values.is_empty() => {
+                let values = values.iter().map(|value| value.to_string()).collect();
+                params.push((name.to_string(), values));
+            }
+            _ => bail!("line {}: expected `name{}value`", i + 1, dialect.delimiter),
+        }
+    }
+    Ok(params)
+}
+
+fn evaluate(args: Args) -> Result<(), anyhow::Error> {
+    // SAFETY: required by clap when there is no subcommand
+    let code_path = args.code_path.unwrap();
 
     // parse code and build AST
-    let ast = {
-        let content = std::fs::read_to_string(&args.code_path)?;
-        let tokens = scan(&content)?;
-        parse(tokens)?
+    let limits = Limits {
+        max_depth: args.max_depth,
+        max_dependencies: args.max_dependencies,
     };
+    // tokens and the ast are emitted before the checks, to debug a model
+    // that doesn't pass them
+    match args.emit {
+        Some(Emit::Tokens) => {
+            let content = std::fs::read_to_string(&code_path)?;
+            let source = if literate::is_literate(&code_path) {
+                literate::extract(&content)
+            } else {
+                content
+            };
+            let tokens: String = scan_spanned(&source)?
+                .into_iter()
+                .map(|(token, span)| format!("{:?} {:?}\n", span, token))
+                .collect();
+            return emit(&tokens, args.emit_file.as_deref());
+        }
+        Some(Emit::Ast) => {
+            let dumped = dump::dump(&load_ast(&code_path)?, dump::FORMAT_VERSION)?;
+            let json = serde_json::to_string_pretty(&dumped)? + "\n";
+            return emit(&json, args.emit_file.as_deref());
+        }
+        _ => {}
+    }
+    let mut timings = Timings::default();
+    let mut model = CompositeModel::new(load_checked_ast(
+        &code_path,
+        &limits,
+        args.deny_warnings,
+        &mut timings,
+    )?);
+    if !model
+        .main
+        .nodes
+        .iter()
+        .any(|node| matches!(node, Node::Cell(_)))
+    {
+        bail!("no cells defined in `{}`", code_path.display());
+    }
+    for link in &args.link {
+        if let Some((path, alias)) = link.split_once(" as ") {
+            let ast = load_checked_ast(
+                &PathBuf::from(path.trim()),
+                &limits,
+                args.deny_warnings,
+                &mut timings,
+            )?;
+            model = model.link(alias.trim(), ast);
+        } else {
+            bail!("invalid link. usage --link \"other.cell as alias\"")
+        }
+    }
+    // read once, every permutation sees the same environment
+    simplify::resolve_env(&mut model.main)?;
+    for link in &mut model.links {
+        simplify::resolve_env(&mut link.ast)?;
+    }
 
     // build params
     let mut param_names = Vec::new();
     let mut params_values = Vec::new();
+    let mut params_files = vec![];
+    for path in &args.params_file {
+        let content = std::fs::read_to_string(path)?;
+        let params = parse_params_csv(&content, args.csv_dialect)
+            .map_err(|e| e.context(format!("invalid params file `{}`", path.display())))?;
+        let mut names = vec![];
+        for (name, values) in params {
+            names.push(name.clone());
+            param_names.push(name);
+            params_values.push((values, args.csv_dialect));
+        }
+        params_files.push((path, names));
+    }
     for param in &args.param {
-        if let Some((name, values_str)) = param.split_once('=') {
-            let mut values = vec![];
-            for value in values_str.split(",") {
-                let value: f64 = value.parse()?;
-                values.push(value);
-            }
-            params_values.push(values);
-            param_names.push(name.to_string());
+        let (name, values) = parse_param(param)?;
+        params_values.push((values, CsvDialect::default()));
+        param_names.push(name);
+    }
+
+    // translate caller-side names to model param names
+    let mut param_map = HashMap::new();
+    for mapping in &args.map {
+        if let Some((from, to)) = mapping.split_once('=') {
+            param_map.insert(from.trim().to_string(), to.trim().to_string());
         } else {
-            bail!("invalid param. usage --param \"name=1\"")
+            bail!("invalid map. usage --map \"price=unit_price\"")
         }
     }
-
-    let permutations: Vec<_> = params_values
-        .into_iter()
-        .multi_cartesian_product()
-        .par_bridge()
+    let model_param_names: Vec<_> = param_names
+        .iter()
+        .map(|name| param_map.get(name).unwrap_or(name).to_string())
+        .collect();
+    let declared = model.params();
+    let types = param_types(
+        model
+            .links
+            .iter()
+            .map(|link| &link.ast)
+            .chain([&model.main]),
+    );
+    let params_values: Vec<_> = params_values
+        .iter()
+        .zip(&model_param_names)
+        .map(|((values, dialect), name)| {
+            typed_values(values, types.get(name.as_str()).copied(), *dialect)
+        })
         .collect();
+    for (path, names) in &params_files {
+        let names: Vec<_> = names
+            .iter()
+            .map(|name| param_map.get(name).unwrap_or(name).as_str())
+            .collect();
+        for diagnostic in stale_params(&names, &declared, &model_param_names) {
+            let path = path.display().to_string();
+            eprintln!("{}", diagnostic.render_with("", &path, color_enabled()));
+        }
+    }
+
+    let permutation_count: usize = params_values.iter().map(Vec::len).product();
     let param_len = param_names.len();
-    let cell_names: Vec<_> = args.query.split(',').collect();
-    let outputs: Vec<_> = permutations
-        .into_iter()
-        .par_bridge()
-        .flat_map(|permutation| {
-            let mut input = HashMap::with_capacity(param_len);
-            for (name, value) in param_names.iter().zip(permutation.iter()) {
-                input.insert(name.to_string(), *value);
+    let query: Vec<String> = match &args.query {
+        Some(query) => {
+            let cells: Vec<_> = model
+                .main
+                .nodes
+                .iter()
+                .filter_map(|node| match node {
+                    Node::Cell(cell) => Some(cell.name.as_str()),
+                    _ => None,
+                })
+                .collect();
+            query::expand(query, &cells)?
+        }
+        None => model
+            .main
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Cell(cell) if cell.output => Some(cell.name.clone()),
+                _ => None,
+            })
+            .collect(),
+    };
+    if query.is_empty() {
+        bail!("the model has no `output` cells. usage --query \"cell\"");
+    }
+    if param_names.is_empty() {
+        eprintln!("no parameters supplied, running a single evaluation");
+    }
+    let cell_names: Vec<_> = query.iter().map(String::as_str).collect();
+    let optimization_started = Instant::now();
+    model.main = sema::prune(&model.main, &cell_names);
+    simplify::inline(&mut model.main, &cell_names);
+    simplify::simplify(&mut model.main, args.compensated_sum);
+    timings.optimization = optimization_started.elapsed();
+    if args.emit == Some(Emit::Simplified) {
+        return emit(&model.main.to_string(), args.emit_file.as_deref());
+    }
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut run_options = ast_interpreter::RunOptions {
+        seed: Some(seed),
+        compensated_sum: args.compensated_sum,
+        fuel: args.fuel,
+        ..Default::default()
+    };
+    for table in &args.table {
+        if let Some((name, path)) = table.split_once('=') {
+            let content = std::fs::read_to_string(path.trim())?;
+            let table = Table::parse_csv(&content, args.csv_dialect)
+                .map_err(|e| e.context(format!("invalid table `{}`", path.trim())))?;
+            run_options.tables.insert(name.trim().to_string(), table);
+        } else {
+            bail!("invalid table. usage --table \"rates=rates.csv\"")
+        }
+    }
+
+    if args.dry_run {
+        let names: Vec<_> = model_param_names.iter().map(String::as_str).collect();
+        model.validate(&names, &cell_names)?;
+        println!("engine: ast interpreter");
+        println!("model: {}", code_path.display());
+        for link in &model.links {
+            println!("link: {}", link.alias);
+        }
+        println!("permutations: {}", permutation_count);
+        println!("query: {}", cell_names.join(", "));
+        println!(
+            "output: {} on stdout",
+            args.format.to_string().to_lowercase()
+        );
+        return Ok(());
+    }
+
+    let baseline = match &args.baseline {
+        Some(path) => Some(Baseline::load(path)?),
+        None => None,
+    };
+
+    // docs of the given params (by caller-side name) and queried cells
+    let mut node_docs = HashMap::new();
+    for node in &model.main.nodes {
+        match node {
+            Node::Param(Param { name, doc, .. }) | Node::Cell(Cell { name, doc, .. }) => {
+                if let Some(doc) = doc {
+                    node_docs.insert(name.as_str(), doc.as_str());
+                }
             }
-            let result = ast_interpreter::run(&ast, cell_names.as_slice(), &input).ok()?;
-            let output = Output {
-                input,
-                output: HashMap::from_iter(result),
-            };
-            Some(output)
+            _ => {}
+        }
+    }
+    let docs: IndexMap<_, _> = param_names
+        .iter()
+        .zip(&model_param_names)
+        .map(|(name, model_name)| (name.as_str(), model_name.as_str()))
+        .chain(cell_names.iter().map(|name| (*name, *name)))
+        .filter_map(|(name, model_name)| {
+            let doc = node_docs.get(model_name)?;
+            Some((name.to_string(), doc.to_string()))
         })
         .collect();
 
+    let evaluation_started = Instant::now();
+    let permutation_nanos = AtomicU64::new(0);
+    let run_permutation = |(index, permutation): (usize, Vec<Value>)| {
+        if run_options.cancellation.is_cancelled() {
+            return None;
+        }
+        let started = Instant::now();
+        let mut input = IndexMap::with_capacity(param_len);
+        let mut params = HashMap::with_capacity(param_len);
+        for ((name, model_name), value) in param_names
+            .iter()
+            .zip(model_param_names.iter())
+            .zip(permutation.iter())
+        {
+            input.insert(name.to_string(), value.clone());
+            params.insert(model_name.to_string(), value.clone());
+        }
+        // every permutation draws its own random numbers
+        let mut options = run_options.stream(index as u64);
+        options.deadline = args
+            .permutation_timeout
+            .map(|timeout| (Instant::now(), timeout));
+        if args.trace {
+            options.trace = Some(TraceBuffer::new());
+        }
+        if args.assert_warnings {
+            options.assert_warnings = Some(WarningBuffer::new());
+        }
+        let result = model.run(cell_names.as_slice(), &params, &options);
+        if let Some(trace) = &options.trace {
+            for entry in trace.take() {
+                eprintln!(
+                    "{:?}({:?}) trace: {} = {}",
+                    code_path, input, entry.cell, entry.value
+                );
+            }
+        }
+        if let Some(warnings) = &options.assert_warnings {
+            for warning in warnings.take() {
+                eprintln!("{:?}({:?}) warning: {}", code_path, input, warning);
+            }
+        }
+        permutation_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        let (output, timed_out) = match result {
+            Ok(result) => (IndexMap::from_iter(result), false),
+            Err(e) if e.is::<TimedOut>() => {
+                let completed = e.downcast::<TimedOut>().unwrap().completed;
+                (IndexMap::from_iter(completed), true)
+            }
+            Err(e) => {
+                // the row is left out, but a failed assertion shouldn't
+                // go unnoticed
+                eprintln!("{:?}({:?}) failed: {:#}", code_path, input, e);
+                return None;
+            }
+        };
+        let baseline = match &baseline {
+            Some(baseline) => baseline.compare(&input, &output),
+            None => IndexMap::new(),
+        };
+        let output = Output {
+            input,
+            output,
+            baseline,
+            seed,
+            timed_out,
+            partial: timed_out,
+            docs: docs.clone(),
+        };
+        Some(output)
+    };
+    let outputs: Vec<_> = if permutation_count == 1 {
+        // one permutation runs right here, without the product or the thread pool
+        let permutation = params_values.into_iter().flatten().collect();
+        run_permutation((0, permutation)).into_iter().collect()
+    } else {
+        params_values
+            .into_iter()
+            .multi_cartesian_product()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .enumerate()
+            .filter_map(run_permutation)
+            .collect()
+    };
+
+    timings.evaluation = evaluation_started.elapsed();
+    timings.permutations = permutation_count;
+    timings.permutation = Duration::from_nanos(permutation_nanos.into_inner());
+    if args.timings {
+        eprint!("{}", timings);
+    }
+
     match args.format {
         OutputFormat::Text => {
             for output in outputs.into_iter() {
-                println!(
-                    "{:?}({:?}) = {:?}",
-                    args.code_path, output.input, output.output
-                );
+                if output.timed_out && output.output.is_empty() {
+                    println!("{:?}({:?}) timed out", code_path, output.input);
+                } else if output.timed_out {
+                    println!(
+                        "{:?}({:?}) timed out, partial {:?}",
+                        code_path, output.input, output.output
+                    );
+                } else if output.baseline.is_empty() {
+                    println!("{:?}({:?}) = {:?}", code_path, output.input, output.output);
+                } else {
+                    let baseline: IndexMap<_, _> = output
+                        .baseline
+                        .iter()
+                        .map(|(cell, comparison)| (cell, &comparison.value))
+                        .collect();
+                    println!(
+                        "{:?}({:?}) = {:?} (baseline {:?})",
+                        code_path, output.input, output.output, baseline
+                    );
+                }
             }
         }
         OutputFormat::Json => {
@@ -118,3 +1102,108 @@ pub fn run() -> Result<(), anyhow::Error> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_csv() {
+        let dialect: CsvDialect = "semicolon".parse().unwrap();
+        let params =
+            parse_params_csv("# usage\nusage;1000,5;2000\nprovider;gcp\n", dialect).unwrap();
+        assert_eq!(
+            params,
+            vec![
+                (
+                    "usage".to_string(),
+                    vec!["1000,5".to_string(), "2000".to_string()]
+                ),
+                ("provider".to_string(), vec!["gcp".to_string()]),
+            ]
+        );
+        assert_eq!(
+            typed_values(&params[0].1, None, dialect),
+            vec![Value::from(1000.5), Value::from(2000f64)]
+        );
+        assert!(parse_params_csv("usage\n", dialect).is_err());
+        let params = parse_params_csv("x,1.5,true\n", CsvDialect::default()).unwrap();
+        assert_eq!(
+            typed_values(&params[0].1, None, CsvDialect::default()),
+            vec![Value::from(1.5), Value::from(true)]
+        );
+    }
+
+    #[test]
+    fn test_typed_params() {
+        let ast = parse(
+            scan_spanned("param zip: string; param usage: number; param x; cell a: 1;").unwrap(),
+        )
+        .unwrap();
+        let types = param_types([&ast]);
+        let dialect = CsvDialect::default();
+        let typed = |param: &str| {
+            let (name, values) = parse_param(param).unwrap();
+            typed_values(&values, types.get(name.as_str()).copied(), dialect)
+        };
+        assert_eq!(
+            typed("zip=01234,10001"),
+            vec![Value::from("01234"), Value::from("10001")]
+        );
+        assert_eq!(typed("usage=10"), vec![Value::from(10f64)]);
+        assert_eq!(
+            typed("x=10,true,gcp"),
+            vec![Value::from(10f64), Value::from(true), Value::from("gcp")]
+        );
+        let params = HashMap::from([
+            ("zip".to_string(), typed("zip=10001").remove(0)),
+            ("usage".to_string(), Value::from(1f64)),
+            ("x".to_string(), Value::from(1f64)),
+        ]);
+        let results = ast_interpreter::run(&ast, &["a"], &params).unwrap();
+        assert_eq!(results, vec![("a".to_string(), Value::from(1f64))]);
+    }
+
+    #[test]
+    fn test_stale_params() {
+        let given = ["usage".to_string(), "regoin".to_string()];
+        let messages: Vec<_> =
+            stale_params(&["usage", "regoin"], &["usage", "region", "tier"], &given)
+                .into_iter()
+                .map(|x| format!("{} ({})", x.message, x.hint.unwrap_or_default()))
+                .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "param `regoin` is not declared by the model (did you mean `region`?)",
+                "param `region` of the model is not set ()",
+                "param `tier` of the model is not set ()",
+            ]
+        );
+        assert!(stale_params(&["a"], &["a"], &["a".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_timings() {
+        let timings = Timings {
+            scan: Duration::from_millis(1),
+            evaluation: Duration::from_millis(10),
+            permutation: Duration::from_millis(16),
+            permutations: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            timings.to_string(),
+            "scan: 1ms\nparse: 0ns\nsema: 0ns\noptimization: 0ns\nevaluation: 10ms (4 permutations, 4ms each on average)\n"
+        );
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(parse_duration("50ms").unwrap(), Duration::from_millis(50));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("50").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+
+use anyhow::bail;
+
+use crate::{
+    ast_interpreter::{self, Params, RunOptions, TimedOut, Value},
+    parser::{Node, AST},
+    sema::is_private,
+};
+
+/*
+
+# infra.cell
+param servers;
+cell total: servers * 120;
+
+# app.cell
+param infra.total;
+cell total: infra.total + 50;
+
+*/
+
+/// A model whose cells are bound, under `alias`, as params of the models
+/// evaluated after it.
+#[derive(Debug)]
+pub struct LinkedModel {
+    pub alias: String,
+    pub ast: AST,
+}
+
+/// A pipeline of independently written models. Every linked model is
+/// evaluated (in link order) before the main model, and a downstream param
+/// named `<alias>.<cell>` receives the value of `cell` from the linked model
+/// `alias`.
+#[derive(Debug)]
+pub struct CompositeModel {
+    pub links: Vec<LinkedModel>,
+    pub main: AST,
+}
+
+impl CompositeModel {
+    pub fn new(main: AST) -> Self {
+        Self {
+            links: vec![],
+            main,
+        }
+    }
+
+    pub fn link(mut self, alias: impl Into<String>, ast: AST) -> Self {
+        self.links.push(LinkedModel {
+            alias: alias.into(),
+            ast,
+        });
+        self
+    }
+
+    /// Cells of the link at `index` which are bound as params by any model
+    /// evaluated after it.
+    fn required_cells(&self, index: usize) -> Vec<String> {
+        let prefix = format!("{}.", self.links[index].alias);
+        let downstream = self.links[index + 1..]
+            .iter()
+            .map(|link| &link.ast)
+            .chain(std::iter::once(&self.main));
+
+        let mut seen = HashSet::new();
+        let mut cells = vec![];
+        for ast in downstream {
+            for node in &ast.nodes {
+                if let Node::Param(param) = node {
+                    if let Some(cell) = param.name.strip_prefix(&prefix) {
+                        if seen.insert(cell) {
+                            cells.push(cell.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    /// The params the caller has to give: those of every model, except the
+    /// ones bound by a link
+    pub fn params(&self) -> Vec<&str> {
+        let aliases: HashSet<_> = self.links.iter().map(|x| x.alias.as_str()).collect();
+        let mut seen = HashSet::new();
+        self.links
+            .iter()
+            .map(|link| &link.ast)
+            .chain(std::iter::once(&self.main))
+            .flat_map(|ast| &ast.nodes)
+            .filter_map(|node| match node {
+                Node::Param(param) => Some(param.name.as_str()),
+                _ => None,
+            })
+            .filter(
+                |name| !matches!(name.split_once('.'), Some((alias, _)) if aliases.contains(alias)),
+            )
+            .filter(|name| seen.insert(*name))
+            .collect()
+    }
+
+    /// Checks, without evaluating anything, that every param of the
+    /// pipeline is either given or bound by a link, and that the queried
+    /// cells exist.
+    pub fn validate(&self, params: &[&str], cell_names: &[&str]) -> Result<(), anyhow::Error> {
+        fn cells(ast: &AST) -> HashSet<&str> {
+            ast.nodes
+                .iter()
+                .filter_map(|node| match node {
+                    Node::Cell(cell) => Some(cell.name.as_str()),
+                    _ => None,
+                })
+                .collect()
+        }
+        fn check_params(
+            ast: &AST,
+            bound: &HashSet<String>,
+            model: &str,
+            problems: &mut Vec<String>,
+        ) {
+            for node in &ast.nodes {
+                if let Node::Param(param) = node {
+                    if !bound.contains(&param.name) {
+                        problems.push(format!("param `{}` of {} is not given", param.name, model));
+                    }
+                }
+            }
+        }
+
+        let mut problems = vec![];
+        let mut bound: HashSet<String> = params.iter().map(|x| x.to_string()).collect();
+        for (index, link) in self.links.iter().enumerate() {
+            let required = self.required_cells(index);
+            if required.is_empty() {
+                continue;
+            }
+            let model = format!("linked model `{}`", link.alias);
+            check_params(&link.ast, &bound, &model, &mut problems);
+            let defined = cells(&link.ast);
+            for cell in required {
+                if !defined.contains(cell.as_str()) {
+                    problems.push(format!("{} has no cell `{}`", model, cell));
+                } else if is_private(&cell) {
+                    problems.push(format!("cell `{}` of {} is private", cell, model));
+                }
+                bound.insert(format!("{}.{}", link.alias, cell));
+            }
+        }
+        check_params(&self.main, &bound, "the model", &mut problems);
+        let defined = cells(&self.main);
+        for cell in cell_names {
+            if !defined.contains(cell) {
+                problems.push(format!("queried cell `{}` is not defined", cell));
+            } else if is_private(cell) {
+                problems.push(format!("queried cell `{}` is private", cell));
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("{}", problems.join("\n"));
+        }
+        Ok(())
+    }
+
+    pub fn run(
+        &self,
+        cell_names: &[&str],
+        params: &Params,
+        options: &RunOptions,
+    ) -> Result<Vec<(String, Value)>, anyhow::Error> {
+        let mut bound = params.clone();
+        for (index, link) in self.links.iter().enumerate() {
+            let cells = self.required_cells(index);
+            if cells.is_empty() {
+                continue;
+            }
+            if let Some(cell) = cells.iter().find(|cell| is_private(cell)) {
+                bail!(
+                    "cell `{}` of linked model `{}` is private",
+                    cell,
+                    link.alias
+                );
+            }
+            let cells: Vec<_> = cells.iter().map(String::as_str).collect();
+            // links draw from their own stream so they don't repeat the
+            // random numbers of the main model
+            let link_options = options.stream(index as u64 + 1);
+            let results =
+                ast_interpreter::run_with_options(&link.ast, &cells, &bound, &link_options)
+                    .map_err(|e| match e.downcast::<TimedOut>() {
+                        // the completed cells of a link aren't queried cells
+                        Ok(timed_out) => TimedOut {
+                            completed: vec![],
+                            ..timed_out
+                        }
+                        .into(),
+                        Err(e) => e.context(format!("in linked model `{}`", link.alias)),
+                    })?;
+            for (name, value) in results {
+                bound.insert(format!("{}.{}", link.alias, name), value);
+            }
+        }
+        if let Some(cell) = cell_names.iter().find(|cell| is_private(cell)) {
+            bail!("cell `{}` is private and can't be queried", cell);
+        }
+        ast_interpreter::run_with_options(&self.main, cell_names, &bound, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{parser, scanner};
+
+    fn parse(code: &str) -> AST {
+        parser::parse(scanner::scan_spanned(code).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let model = CompositeModel::new(parse(
+            r#"
+            param infra.total;
+            param margin;
+            cell price: infra.total + margin;
+            "#,
+        ))
+        .link(
+            "infra",
+            parse(
+                r#"
+            param servers;
+            cell total: servers * 10;
+            "#,
+            ),
+        );
+        let params = HashMap::from([
+            ("servers".to_string(), Value::from(3f64)),
+            ("margin".to_string(), Value::from(5f64)),
+        ]);
+        assert_eq!(
+            model
+                .run(&["price"], &params, &RunOptions::default())
+                .unwrap(),
+            vec![("price".to_string(), Value::from(35f64))]
+        );
+    }
+
+    #[test]
+    fn test_link_chain() {
+        let model = CompositeModel::new(parse("param b.y; cell z: b.y * 2;"))
+            .link("a", parse("param input; cell x: input + 1;"))
+            .link("b", parse("param a.x; cell y: a.x * 10;"));
+        let params = HashMap::from([("input".to_string(), Value::from(1f64))]);
+        assert_eq!(
+            model.run(&["z"], &params, &RunOptions::default()).unwrap(),
+            vec![("z".to_string(), Value::from(40f64))]
+        );
+    }
+
+    #[test]
+    fn test_link_missing_param() {
+        let model = CompositeModel::new(parse("param infra.total; cell a: infra.total;"))
+            .link("infra", parse("param servers; cell total: servers;"));
+        assert!(model
+            .run(&["a"], &HashMap::new(), &RunOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_private() {
+        let model = CompositeModel::new(parse("param infra._x; cell _a: infra._x; cell b: _a;"))
+            .link("infra", parse("cell _x: 1;"));
+        let params = HashMap::new();
+        assert!(model.run(&["b"], &params, &RunOptions::default()).is_err());
+        assert_eq!(
+            model.validate(&[], &["b", "_a"]).unwrap_err().to_string(),
+            "cell `_x` of linked model `infra` is private\n\
+             queried cell `_a` is private"
+        );
+
+        let model = CompositeModel::new(parse("cell _a: 1; cell b: _a + 1;"));
+        assert!(model.run(&["_a"], &params, &RunOptions::default()).is_err());
+        assert_eq!(
+            model.run(&["b"], &params, &RunOptions::default()).unwrap(),
+            vec![("b".to_string(), Value::from(2f64))]
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let model = CompositeModel::new(parse("param infra.total; param margin; param other.x;"))
+            .link(
+                "infra",
+                parse("param servers; param margin; cell total: servers;"),
+            );
+        assert_eq!(model.params(), vec!["servers", "margin", "other.x"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let model = CompositeModel::new(parse(
+            "param infra.total; param infra.extra; param margin; cell a: infra.total + margin;",
+        ))
+        .link("infra", parse("param servers; cell total: servers;"));
+        assert!(model.validate(&["servers", "margin"], &["a"]).is_err());
+
+        let model = CompositeModel::new(parse("param infra.total; cell a: infra.total;"))
+            .link("infra", parse("param servers; cell total: servers;"));
+        assert!(model.validate(&["servers"], &["a"]).is_ok());
+        assert_eq!(
+            model.validate(&[], &["a", "b"]).unwrap_err().to_string(),
+            "param `servers` of linked model `infra` is not given\n\
+             queried cell `b` is not defined"
+        );
+    }
+}
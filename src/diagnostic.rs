@@ -0,0 +1,329 @@
+use std::{fmt::Display, ops::Range};
+
+/// Char offsets into the source code, `start..end`
+pub type Span = Range<usize>;
+
+/// An error (or warning) pointing at a location in a model's source code.
+///
+/// ```text
+/// expected `;` after the cell expression, found `cell`
+///  --> app.cell:3:1
+///   |
+/// 3 | cell b: 2;
+///   | ^^^^
+///   = hint: every cell ends with `;`
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub hint: Option<String>,
+    /// a second location the message refers to, e.g. the first definition
+    /// of a duplicate, in the same source
+    pub note: Option<(Span, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// the model still runs, rendered with a `warning: ` prefix
+    Warning,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            hint: None,
+            note: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, note: impl Into<String>) -> Self {
+        self.note = Some((span, note.into()));
+        self
+    }
+
+    /// Renders the diagnostic with the offending source line and a caret
+    /// under the span. `path` is only used for the location header.
+    pub fn render(&self, source: &str, path: &str) -> String {
+        self.render_with(source, path, false)
+    }
+
+    /// `render`, highlighted with ANSI colors when `color` is set
+    pub fn render_with(&self, source: &str, path: &str, color: bool) -> String {
+        let paint = |text: &str, style: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", style, text)
+            } else {
+                text.to_string()
+            }
+        };
+        let accent = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut out = match self.severity {
+            Severity::Error => format!("{}\n", paint(&self.message, BOLD)),
+            Severity::Warning => format!(
+                "{}{}\n",
+                paint("warning: ", accent),
+                paint(&self.message, BOLD)
+            ),
+        };
+        if let Some(span) = &self.span {
+            out.push_str(&snippet(source, path, span, accent, color));
+        } else {
+            out.push_str(&format!(" {} {}\n", paint("-->", BLUE), path));
+        }
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("  {} {}\n", paint("= hint:", CYAN), hint));
+        }
+        if let Some((span, note)) = &self.note {
+            out.push_str(&render_note(note, source, path, span, color));
+        }
+        out
+    }
+}
+
+/// `= note: ...` and the source line `span` points to
+pub fn render_note(note: &str, source: &str, path: &str, span: &Span, color: bool) -> String {
+    let label = if color {
+        format!("\x1b[{}m= note:\x1b[0m", CYAN)
+    } else {
+        "= note:".to_string()
+    };
+    format!(
+        "  {} {}\n{}",
+        label,
+        note,
+        snippet(source, path, span, CYAN, color)
+    )
+}
+
+/// The location header, the line `span` starts on and a caret under it
+fn snippet(source: &str, path: &str, span: &Span, accent: &str, color: bool) -> String {
+    let paint = |text: &str, style: &str| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            text.to_string()
+        }
+    };
+    let (line_no, column, line) = locate(source, span.start);
+    let gutter = " ".repeat(line_no.to_string().len());
+    let width = span
+        .end
+        .saturating_sub(span.start)
+        .clamp(1, (line.chars().count() + 1).saturating_sub(column).max(1));
+    let mut out = format!(
+        "{}{} {}:{}:{}\n",
+        gutter,
+        paint("-->", BLUE),
+        path,
+        line_no,
+        column
+    );
+    out.push_str(&format!("{}\n", paint(&format!("{} |", gutter), BLUE)));
+    out.push_str(&format!(
+        "{} {}\n",
+        paint(&format!("{} |", line_no), BLUE),
+        line
+    ));
+    out.push_str(&format!(
+        "{} {}{}\n",
+        paint(&format!("{} |", gutter), BLUE),
+        " ".repeat(column - 1),
+        paint(&"^".repeat(width), accent)
+    ));
+    out
+}
+
+/// 1-based line and column of the char at `offset`, plus the line itself.
+pub fn locate(source: &str, offset: usize) -> (usize, usize, &str) {
+    let mut line_start = 0;
+    let mut line_no = 1;
+    for (i, (byte_idx, c)) in source.char_indices().enumerate() {
+        if i == offset {
+            break;
+        }
+        if c == '\n' {
+            line_no += 1;
+            line_start = byte_idx + 1;
+        }
+    }
+    let line = source[line_start..].lines().next().unwrap_or("");
+    let column = offset - source[..line_start].chars().count() + 1;
+    (line_no, column, line)
+}
+
+// ANSI styles used by `render_with`
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+const CYAN: &str = "1;36";
+
+/// The candidate closest to a misspelled `name`, if any is close enough to
+/// be what was meant.
+pub fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    // a third of the name may be wrong, but never all of it
+    let len = name.chars().count();
+    let max_distance = (len / 3).max(1).min(len.saturating_sub(1));
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "cell a: 1;\ncell b: 2 $;\n";
+        let diagnostic = Diagnostic::new("unexpected character `$`")
+            .with_span(21..22)
+            .with_hint("remove it");
+        assert_eq!(
+            diagnostic.render(source, "app.cell"),
+            "unexpected character `$`\n \
+             --> app.cell:2:11\n  \
+               |\n\
+             2 | cell b: 2 $;\n  \
+               |           ^\n  \
+               = hint: remove it\n"
+        );
+    }
+
+    #[test]
+    fn test_render_note() {
+        let source = "cell a: 1;\ncell a: 2;\n";
+        let diagnostic = Diagnostic::new("`a` is defined twice")
+            .with_span(11..21)
+            .with_note(0..10, "first defined here");
+        assert_eq!(
+            diagnostic.render(source, "app.cell"),
+            "`a` is defined twice\n \
+             --> app.cell:2:1\n  \
+               |\n\
+             2 | cell a: 2;\n  \
+               | ^^^^^^^^^^\n  \
+               = note: first defined here\n \
+             --> app.cell:1:1\n  \
+               |\n\
+             1 | cell a: 1;\n  \
+               | ^^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let names = ["cpu_cost", "cpus", "provider"];
+        assert_eq!(did_you_mean("cpu_cots", names), Some("cpu_cost"));
+        assert_eq!(did_you_mean("cpu", names), Some("cpus"));
+        assert_eq!(did_you_mean("providr", names), Some("provider"));
+        assert_eq!(did_you_mean("memory", names), None);
+        assert_eq!(did_you_mean("cpus", names), None);
+        assert_eq!(did_you_mean("a", ["b"]), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_render_colored() {
+        let diagnostic = Diagnostic::warning("unused")
+            .with_span(0..1)
+            .with_hint("remove it");
+        let rendered = diagnostic.render_with("a", "app.cell", true);
+        assert_eq!(
+            rendered,
+            "\x1b[1;33mwarning: \x1b[0m\x1b[1munused\x1b[0m\n \
+             \x1b[1;34m-->\x1b[0m app.cell:1:1\n\
+             \x1b[1;34m  |\x1b[0m\n\
+             \x1b[1;34m1 |\x1b[0m a\n\
+             \x1b[1;34m  |\x1b[0m \x1b[1;33m^\x1b[0m\n  \
+             \x1b[1;36m= hint:\x1b[0m remove it\n"
+        );
+        assert_eq!(
+            diagnostic.render("a", "app.cell"),
+            diagnostic.render_with("a", "app.cell", false)
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let diagnostic = Diagnostic::warning("unused param `a`");
+        assert_eq!(
+            diagnostic.render("", "app.cell"),
+            "warning: unused param `a`\n --> app.cell\n"
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_input() {
+        let source = "cell a: 1";
+        let diagnostic = Diagnostic::new("expected `;`").with_span(9..9);
+        assert_eq!(
+            diagnostic.render(source, "app.cell"),
+            "expected `;`\n \
+             --> app.cell:1:10\n  \
+               |\n\
+             1 | cell a: 1\n  \
+               |          ^\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::bail;
+use serde_json::{json, Value};
+
+use crate::{parser::AST, types};
+
+/*
+
+$ cell-script dump app.cell
+{
+  "format_version": 1,
+  "nodes": [
+    { "param": { "name": "a", "type": null, "doc": null, "span": { "start": 0, "end": 8 } } },
+    ...
+  ],
+  "types": { "b": "number" }
+}
+
+Tools built against the dump read `format_version` first. Adding a field
+keeps the version; renaming or removing one bumps it, and the previous
+versions stay available through `--format-version` for as long as they're
+listed in `SUPPORTED`.
+
+*/
+
+/// The version `dump` writes by default
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Every version `dump` can still write
+pub const SUPPORTED: &[u32] = &[1];
+
+/// The AST as JSON in the given format version
+pub fn dump(ast: &AST, version: u32) -> Result<Value, anyhow::Error> {
+    match version {
+        1 => {
+            let types: serde_json::Map<_, _> = types::check(ast)
+                .0
+                .into_iter()
+                .map(|(name, ty)| (name, Value::from(ty.to_string())))
+                .collect();
+            Ok(json!({
+                "format_version": 1,
+                "nodes": serde_json::to_value(&ast.nodes)?,
+                "types": types,
+            }))
+        }
+        _ => bail!(
+            "unsupported dump format version {}, supported versions are {:?}",
+            version,
+            SUPPORTED
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, scanner};
+
+    #[test]
+    fn test_dump() {
+        let ast = parser::parse(scanner::scan_spanned("param a; cell b: a + 1;").unwrap()).unwrap();
+        let dumped = dump(&ast, FORMAT_VERSION).unwrap();
+        assert_eq!(dumped["format_version"], 1);
+        assert_eq!(dumped["types"], json!({ "b": "number" }));
+        assert_eq!(
+            dumped["nodes"][0],
+            json!({
+                "param": { "name": "a", "type": null, "doc": null, "span": { "start": 0, "end": 8 } }
+            })
+        );
+        assert_eq!(
+            dumped["nodes"][1]["cell"]["expr"],
+            json!({ "add": [{ "atom": { "ident": "a" } }, { "atom": { "number": 1.0 } }] })
+        );
+        assert!(dump(&ast, 0).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use sha2::{Digest, Sha256};
+
+use crate::parser::{Node, AST};
+
+/// SHA-256 over the canonical printing of the model. Comments, whitespace,
+/// redundant parentheses and the order of declarations don't affect the
+/// hash; directives keep their relative order since later ones may
+/// override earlier ones.
+pub fn hash(ast: &AST) -> String {
+    let mut directives = vec![];
+    let mut declarations = vec![];
+    for node in &ast.nodes {
+        match node {
+            Node::Directive(_) => directives.push(node.to_string()),
+            Node::Param(_) | Node::Cell(_) | Node::Import(_) | Node::Assert(_) => {
+                declarations.push(node.to_string())
+            }
+        }
+    }
+    declarations.sort();
+
+    let mut hasher = Sha256::new();
+    for line in directives.iter().chain(declarations.iter()) {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, scanner};
+
+    fn hash_of(code: &str) -> String {
+        hash(&parser::parse(scanner::scan_spanned(code).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_formatting_only() {
+        let a = hash_of("param a;\ncell b: a * 2 + 1;\n");
+        assert_eq!(a.len(), 64);
+        assert_eq!(
+            a,
+            hash_of("# the input\nparam   a ;\n\n\ncell b:\n   (a * 2) + 1\n;")
+        );
+        assert_eq!(a, hash_of("cell b: a * 2 + 1; param a;"));
+    }
+
+    #[test]
+    fn test_semantic_change() {
+        let a = hash_of("param a; cell b: a * 2 + 1;");
+        assert_ne!(a, hash_of("param a; cell b: a * (2 + 1);"));
+        assert_ne!(a, hash_of("param a; cell c: a * 2 + 1;"));
+        assert_ne!(a, hash_of("@eq-tolerance(1) param a; cell b: a * 2 + 1;"));
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use crate::{
+    diagnostic::{render_note, Diagnostic, Span},
+    literate,
+    parser::{Atom, Expr, Node, AST},
+    sema::children_mut,
+};
+
+/*
+
+# main.cell
+import "costs.cell";
+cell total: compute + storage;
+
+# costs.cell
+param servers;
+cell compute: servers * 120;
+cell storage: 40;
+
+# with `import "costs.cell" as costs;` the same cells are `costs.compute`
+# and `costs.storage`, and the param is `costs.servers`
+
+*/
+
+/// The files of a model and its imports, laid out one after the other. The
+/// spans of the merged AST are offsets into this layout, so a span
+/// identifies both a file and a location in it.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>,
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub content: String,
+    /// offset of the first char of the file in the layout
+    pub start: usize,
+}
+
+impl SourceMap {
+    /// Appends a file to the layout, returning its `start`
+    pub fn add(&mut self, path: PathBuf, content: String) -> usize {
+        // one extra char so spans at the end of a file stay inside it
+        let start = match self.files.last() {
+            Some(file) => file.start + file.content.chars().count() + 1,
+            None => 0,
+        };
+        self.files.push(SourceFile {
+            path,
+            content,
+            start,
+        });
+        start
+    }
+
+    /// The file containing `offset`, and `offset` relative to it
+    pub fn locate(&self, offset: usize) -> (&SourceFile, usize) {
+        let idx = self
+            .files
+            .partition_point(|file| file.start <= offset)
+            .saturating_sub(1);
+        let file = &self.files[idx];
+        (file, offset - file.start)
+    }
+
+    /// Renders a diagnostic of the merged AST against the file it points to
+    pub fn render(&self, diagnostic: &Diagnostic, color: bool) -> String {
+        let span = match (&diagnostic.span, self.files.is_empty()) {
+            (Some(span), false) => span,
+            _ => {
+                let path = self.files.first().map(|x| x.path.display().to_string());
+                return diagnostic.render_with("", &path.unwrap_or_default(), color);
+            }
+        };
+        let (file, start) = self.locate(span.start);
+        let local = Diagnostic {
+            span: Some(start..start + (span.end - span.start)),
+            note: None,
+            ..diagnostic.clone()
+        };
+        let mut out = local.render_with(&file.content, &file.path.display().to_string(), color);
+        // the note may point into another file
+        if let Some((span, note)) = &diagnostic.note {
+            let (file, start) = self.locate(span.start);
+            out.push_str(&render_note(
+                note,
+                &file.content,
+                &file.path.display().to_string(),
+                &(start..start + (span.end - span.start)),
+                color,
+            ));
+        }
+        out
+    }
+}
+
+/// Loads the model at `path` and, recursively, the files it imports. The
+/// nodes of an imported file take the place of its `import`; a file
+/// imported more than once into the same namespace is only included the
+/// first time. `parse` turns the content of a file into its AST.
+pub fn load(
+    path: &Path,
+    parse: &mut dyn FnMut(&Path, &str) -> Result<AST, anyhow::Error>,
+) -> Result<(AST, SourceMap), anyhow::Error> {
+    let mut loader = Loader {
+        parse,
+        stack: vec![],
+        files: HashMap::new(),
+        map: SourceMap::default(),
+    };
+    let mut scope = HashSet::new();
+    let nodes = loader.load(path, &mut scope)?;
+    Ok((AST { nodes }, loader.map))
+}
+
+struct Loader<'a> {
+    parse: &'a mut dyn FnMut(&Path, &str) -> Result<AST, anyhow::Error>,
+    /// files being loaded, to detect import cycles
+    stack: Vec<PathBuf>,
+    /// index in `map` of the files read so far
+    files: HashMap<PathBuf, usize>,
+    map: SourceMap,
+}
+
+impl Loader<'_> {
+    /// The nodes of the file at `path` and of its imports. `scope` holds the
+    /// files already included in the namespace being loaded.
+    fn load(
+        &mut self,
+        path: &Path,
+        scope: &mut HashSet<PathBuf>,
+    ) -> Result<Vec<Node>, anyhow::Error> {
+        let canonical = path.canonicalize().map_err(|e| {
+            anyhow::Error::new(e).context(format!("cannot read `{}`", path.display()))
+        })?;
+        if let Some(idx) = self.stack.iter().position(|x| *x == canonical) {
+            let cycle: Vec<_> = self.stack[idx..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|x| x.display().to_string())
+                .collect();
+            bail!("import cycle: {}", cycle.join(" -> "));
+        }
+        if !scope.insert(canonical.clone()) {
+            return Ok(vec![]);
+        }
+
+        // a file imported into several namespaces is read once, but parsed
+        // for each of them
+        let idx = match self.files.get(&canonical) {
+            Some(idx) => *idx,
+            None => {
+                let content = std::fs::read_to_string(path)?;
+                self.map.add(path.to_path_buf(), content);
+                self.files
+                    .insert(canonical.clone(), self.map.files.len() - 1);
+                self.map.files.len() - 1
+            }
+        };
+        let file = &self.map.files[idx];
+        let offset = file.start;
+        let source = if literate::is_literate(path) {
+            literate::extract(&file.content)
+        } else {
+            file.content.clone()
+        };
+        let ast = (self.parse)(path, &source)?;
+
+        self.stack.push(canonical);
+        let mut nodes = vec![];
+        for mut node in ast.nodes {
+            if let Node::Import(import) = &node {
+                let imported = path
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(&import.path);
+                let loaded = match &import.alias {
+                    Some(alias) => self
+                        .load(&imported, &mut HashSet::new())
+                        .map(|nodes| namespace(nodes, alias)),
+                    None => self.load(&imported, scope),
+                };
+                nodes.extend(
+                    loaded.map_err(|e| e.context(format!("imported from `{}`", path.display())))?,
+                );
+                continue;
+            }
+            shift(&mut node, offset);
+            nodes.push(node);
+        }
+        self.stack.pop();
+        Ok(nodes)
+    }
+}
+
+/// Prefixes the params and cells declared by `nodes` with `alias.`, along
+/// with the references to them. Other names are left alone, they refer to
+/// the importing model.
+fn namespace(mut nodes: Vec<Node>, alias: &str) -> Vec<Node> {
+    let declared: HashSet<String> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Param(x) => Some(x.name.clone()),
+            Node::Cell(x) => Some(x.name.clone()),
+            _ => None,
+        })
+        .collect();
+    let rename = |name: &mut String| {
+        if declared.contains(name) {
+            *name = format!("{}.{}", alias, name);
+        }
+    };
+    for node in &mut nodes {
+        match node {
+            Node::Param(param) => rename(&mut param.name),
+            Node::Cell(cell) => {
+                rename(&mut cell.name);
+                rename_identifiers(&mut cell.expr, &rename);
+            }
+            Node::Assert(assert) => rename_identifiers(&mut assert.expr, &rename),
+            Node::Directive(_) | Node::Import(_) => {}
+        }
+    }
+    nodes
+}
+
+fn rename_identifiers(expr: &mut Expr, rename: &dyn Fn(&mut String)) {
+    if let Expr::Atom(Atom::Ident(name)) = expr {
+        rename(name);
+    }
+    for child in children_mut(expr) {
+        rename_identifiers(child, rename);
+    }
+}
+
+fn shift(node: &mut Node, offset: usize) {
+    let span: &mut Span = match node {
+        Node::Param(x) => &mut x.span,
+        Node::Cell(x) => &mut x.span,
+        Node::Directive(x) => &mut x.span,
+        Node::Assert(x) => &mut x.span,
+        Node::Import(x) => &mut x.span,
+    };
+    *span = span.start + offset..span.end + offset;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast_interpreter, parser, scanner};
+
+    fn parse(_: &Path, content: &str) -> Result<AST, anyhow::Error> {
+        scanner::scan_spanned(content).and_then(parser::parse)
+    }
+
+    /// A fresh directory with `files` written into it
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cell-script-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = write_files(
+            "import",
+            &[
+                (
+                    "main.cell",
+                    "import \"lib/costs.cell\";\ncell total: compute + storage;",
+                ),
+                (
+                    "lib/costs.cell",
+                    "import \"storage.cell\";\nparam servers;\ncell compute: servers * 120;",
+                ),
+                ("lib/storage.cell", "cell storage: 40;"),
+            ],
+        );
+        let (ast, map) = load(&dir.join("main.cell"), &mut parse).unwrap();
+        let params = [("servers".to_string(), 2f64.into())].into();
+        assert_eq!(
+            ast_interpreter::run(&ast, &["total"], &params).unwrap()[0].1,
+            280f64
+        );
+        assert_eq!(map.files.len(), 3);
+
+        // spans point into the file the node came from
+        let compute = ast
+            .nodes
+            .iter()
+            .find_map(|node| match node {
+                Node::Cell(cell) if cell.name == "compute" => Some(cell.span.clone()),
+                _ => None,
+            })
+            .unwrap();
+        let (file, start) = map.locate(compute.start);
+        assert!(file.path.ends_with("lib/costs.cell"));
+        assert_eq!(start, 38);
+        let rendered = map.render(&Diagnostic::warning("w").with_span(compute), false);
+        assert!(rendered.contains("costs.cell:3:1"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.cell", "import \"b.cell\"; cell a: 1;"),
+                ("b.cell", "import \"a.cell\"; cell b: 1;"),
+            ],
+        );
+        let error = load(&dir.join("a.cell"), &mut parse).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("import cycle"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_diamond() {
+        let dir = write_files(
+            "diamond",
+            &[
+                ("main.cell", "import \"a.cell\"; import \"b.cell\";"),
+                ("a.cell", "import \"shared.cell\";"),
+                ("b.cell", "import \"shared.cell\";"),
+                ("shared.cell", "cell shared: 1;"),
+            ],
+        );
+        let (ast, _) = load(&dir.join("main.cell"), &mut parse).unwrap();
+        assert_eq!(ast.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_namespace() {
+        let dir = write_files(
+            "namespace",
+            &[
+                (
+                    "main.cell",
+                    "import \"aws.cell\" as aws; import \"gcp.cell\" as gcp;\n\
+                     param margin;\n\
+                     cell cost: min(aws.cost, gcp.cost) + margin;",
+                ),
+                (
+                    "aws.cell",
+                    "param servers; cell cost: servers * 10 + margin;",
+                ),
+                (
+                    "gcp.cell",
+                    "import \"base.cell\" as base; cell cost: base.cost * 2;",
+                ),
+                ("base.cell", "cell cost: 4;"),
+            ],
+        );
+        let (ast, map) = load(&dir.join("main.cell"), &mut parse).unwrap();
+        assert_eq!(
+            ast.to_string(),
+            "param aws.servers;\n\
+             cell aws.cost: aws.servers * 10 + margin;\n\
+             cell gcp.base.cost: 4;\n\
+             cell gcp.cost: gcp.base.cost * 2;\n\
+             param margin;\n\
+             cell cost: min(aws.cost, gcp.cost) + margin;\n"
+        );
+        let params = [
+            ("aws.servers".to_string(), 1f64.into()),
+            ("margin".to_string(), 1f64.into()),
+        ]
+        .into();
+        assert_eq!(
+            ast_interpreter::run(&ast, &["cost"], &params).unwrap()[0].1,
+            9f64
+        );
+        assert_eq!(map.files.len(), 4);
+    }
+}
@@ -0,0 +1,49 @@
+/*
+
+The engine behind the `cell-script` binary, for embedding it:
+
+let mut ast = parser::parse(scanner::scan_spanned(code)?)?;
+simplify::resolve_env(&mut ast)?;
+let mut options = ast_interpreter::RunOptions::default();
+options.register("region_price", ast_interpreter::HostFunction::new(2, |args| args[0] * args[1]))?;
+let values = ast_interpreter::run_with_options(&ast, &["total"], &params, &options)?;
+
+Evaluation recurses up to MAX_EVALUATION_DEPTH levels, run it on a thread
+with a STACK_SIZE stack, as the binary does, or a deep model can overflow
+the default stack of a thread:
+
+let values = std::thread::scope(|scope| {
+    std::thread::Builder::new()
+        .stack_size(ast_interpreter::STACK_SIZE)
+        .spawn_scoped(scope, || ast_interpreter::run_with_options(&ast, &["total"], &params, &options))
+        .unwrap()
+        .join()
+        .unwrap()
+})?;
+
+*/
+
+pub mod ast_interpreter;
+pub mod baseline;
+pub mod cli;
+pub mod composite;
+pub mod diagnostic;
+pub mod dump;
+pub mod hash;
+pub mod import;
+pub mod literate;
+pub mod mutate;
+pub mod parser;
+pub mod printer;
+pub mod query;
+pub mod report;
+pub mod scanner;
+pub mod sdiff;
+pub mod sema;
+pub mod simplify;
+pub mod summation;
+pub mod table;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod types;
+pub mod workspace;
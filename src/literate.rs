@@ -0,0 +1,246 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    ast_interpreter::Value,
+    parser::{parse, Node},
+    scanner::scan_spanned,
+};
+
+/*
+
+# Pricing
+
+The fee is a flat 2% of the order, with a minimum of 1.
+
+```cell
+param order;
+cell fee: max(order * 0.02, 1);
+```
+
+Only the fenced `cell` blocks of a `.cell.md` file are part of the model,
+the prose documents it. `cell-script render` turns the file into an HTML
+page with the prose, the code blocks and the values of their cells.
+
+*/
+
+/// Whether `path` is a literate model, `name.cell.md`
+pub fn is_literate(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".cell.md")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Section<'a> {
+    /// markdown between the code blocks
+    Prose(Vec<&'a str>),
+    /// the content of a fenced `cell` block
+    Code(Vec<&'a str>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line {
+    Prose,
+    /// the opening or closing fence of a `cell` block
+    Fence,
+    Code,
+}
+
+/// What each line of a literate model is. Fenced blocks in other
+/// languages are prose.
+fn classify(markdown: &str) -> Vec<(&str, Line)> {
+    let mut lines = vec![];
+    // the fence of the block being read, and whether it's a `cell` block
+    let mut fence: Option<(&str, bool)> = None;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let kind = match fence {
+            Some((open, is_cell)) if trimmed == open => {
+                fence = None;
+                if is_cell {
+                    Line::Fence
+                } else {
+                    Line::Prose
+                }
+            }
+            Some((_, true)) => Line::Code,
+            Some((_, false)) => Line::Prose,
+            None => match fence_of(trimmed) {
+                Some((open, info)) => {
+                    fence = Some((open, info == "cell"));
+                    if info == "cell" {
+                        Line::Fence
+                    } else {
+                        Line::Prose
+                    }
+                }
+                None => Line::Prose,
+            },
+        };
+        lines.push((line, kind));
+    }
+    lines
+}
+
+/// Splits a literate model into its prose and its `cell` blocks, in order
+pub fn sections(markdown: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![];
+    let mut prose = vec![];
+    let mut code = None;
+    for (line, kind) in classify(markdown) {
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+        match (kind, &mut code) {
+            (Line::Fence, None) => {
+                if !prose.is_empty() {
+                    sections.push(Section::Prose(std::mem::take(&mut prose)));
+                }
+                code = Some(vec![]);
+            }
+            (Line::Fence, Some(_)) => sections.push(Section::Code(code.take().unwrap())),
+            (Line::Code, Some(lines)) => lines.push(line),
+            _ => prose.push(line),
+        }
+    }
+    if let Some(lines) = code {
+        sections.push(Section::Code(lines));
+    }
+    if !prose.is_empty() {
+        sections.push(Section::Prose(prose));
+    }
+    sections
+}
+
+/// The opening fence of a code block and its info string, e.g.
+/// ("```", "cell")
+fn fence_of(line: &str) -> Option<(&str, &str)> {
+    let marker = line.chars().next().filter(|x| *x == '`' || *x == '~')?;
+    let len = line.chars().take_while(|x| *x == marker).count();
+    if len < 3 {
+        return None;
+    }
+    Some((&line[..len], line[len..].trim()))
+}
+
+/// The source code of a literate model. Everything outside the `cell`
+/// blocks is blanked out, so the offsets of the code, and the diagnostics
+/// pointing at it, are the same as in the markdown file.
+pub fn extract(markdown: &str) -> String {
+    classify(markdown)
+        .into_iter()
+        .map(|(line, kind)| match kind {
+            Line::Code => line.to_string(),
+            _ => line
+                .chars()
+                .map(|x| if x == '\n' { '\n' } else { ' ' })
+                .collect(),
+        })
+        .collect()
+}
+
+/// An HTML page of a literate model: its prose, its `cell` blocks, and
+/// after each block the values its cells evaluated to in `results`
+pub fn render(title: &str, markdown: &str, results: &HashMap<String, Value>) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n",
+        escape(title)
+    );
+    for section in sections(markdown) {
+        match section {
+            Section::Prose(lines) => render_prose(&mut html, &lines),
+            Section::Code(lines) => {
+                let code = lines.join("\n");
+                html.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&code)));
+                let cells: Vec<_> = scan_spanned(&code)
+                    .and_then(parse)
+                    .map(|ast| ast.nodes)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|node| match node {
+                        Node::Cell(cell) => Some(cell.name),
+                        _ => None,
+                    })
+                    .filter_map(|name| results.get(&name).map(|value| (name, value)))
+                    .collect();
+                if cells.is_empty() {
+                    continue;
+                }
+                html.push_str("<table>\n");
+                for (name, value) in cells {
+                    html.push_str(&format!(
+                        "<tr><td><code>{}</code></td><td>{}</td></tr>\n",
+                        escape(&name),
+                        escape(&value.to_string())
+                    ));
+                }
+                html.push_str("</table>\n");
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Headings and paragraphs; other markdown is kept as text
+fn render_prose(html: &mut String, lines: &[&str]) {
+    fn flush(html: &mut String, paragraph: &mut Vec<&str>) {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", escape(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+    }
+    let mut paragraph = vec![];
+    for line in lines {
+        let level = line.chars().take_while(|x| *x == '#').count();
+        if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+            flush(html, &mut paragraph);
+            html.push_str(&format!(
+                "<h{0}>{1}</h{0}>\n",
+                level,
+                escape(line[level..].trim())
+            ));
+        } else if line.trim().is_empty() {
+            flush(html, &mut paragraph);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    flush(html, &mut paragraph);
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "# Fees\n\nA flat fee.\n\n```cell\ncell fee: 2;\n```\n\n```sh\nls\n```\n";
+
+    #[test]
+    fn test_extract() {
+        assert_eq!(
+            sections(MODEL),
+            vec![
+                Section::Prose(vec!["# Fees", "", "A flat fee.", ""]),
+                Section::Code(vec!["cell fee: 2;"]),
+                Section::Prose(vec!["", "```sh", "ls", "```"]),
+            ]
+        );
+        let code = extract(MODEL);
+        assert_eq!(code.chars().count(), MODEL.chars().count());
+        assert_eq!(code.trim(), "cell fee: 2;");
+        assert_eq!(code.find("cell"), MODEL.find("cell fee"));
+    }
+
+    #[test]
+    fn test_render() {
+        let results = HashMap::from([("fee".to_string(), Value::from(2f64))]);
+        let html = render("fees", MODEL, &results);
+        assert!(html.contains("<h1>Fees</h1>\n<p>A flat fee.</p>\n"));
+        assert!(html.contains("<pre><code>cell fee: 2;</code></pre>\n"));
+        assert!(html.contains("<tr><td><code>fee</code></td><td>2</td></tr>"));
+        assert!(html.contains("<p>```sh\nls\n```</p>"));
+    }
+}
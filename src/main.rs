@@ -1,10 +1,20 @@
-pub mod ast_interpreter;
-pub mod cli;
-pub mod parser;
-pub mod scanner;
+use cell_script::{ast_interpreter, cli};
 
 fn main() {
-    if let Err(e) = cli::run() {
-        eprintln!("[Error] {}", e);
+    // evaluation recurses up to `ast_interpreter::MAX_EVALUATION_DEPTH`
+    // levels, deeper than the default stacks fit
+    rayon::ThreadPoolBuilder::new()
+        .stack_size(ast_interpreter::STACK_SIZE)
+        .build_global()
+        .expect("the global thread pool is only built here");
+    let result = std::thread::Builder::new()
+        .stack_size(ast_interpreter::STACK_SIZE)
+        .spawn(cli::run)
+        .expect("failed to spawn the main thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+    if let Err(e) = result {
+        eprintln!("[Error] {:#}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,206 @@
+use anyhow::bail;
+use rayon::prelude::*;
+
+use crate::{
+    ast_interpreter::{self, Params},
+    parser::{Atom, Expr, Node, Operator, AST},
+    sema::children_mut,
+};
+
+/*
+
+Mutation testing: every mutant is the model with one small change, e.g.
+
+  cell total: base + fee;    ->    cell total: base - fee;
+  cell fee: if a > 10 ? ...  ->    cell fee: if a >= 10 ? ...
+  cell rate: 0.2;            ->    cell rate: 0.22;
+
+A mutant is killed when one of the model's `assert`s (or an evaluation
+error) catches it for some permutation of the params. Surviving mutants
+point at logic the assertions don't pin down.
+
+*/
+
+#[derive(Debug)]
+pub struct Mutant {
+    /// the mutated cell
+    pub cell: String,
+    /// e.g. "`+` -> `-`"
+    pub description: String,
+    pub ast: AST,
+}
+
+/// One mutant per possible change to the expressions of the model's cells
+pub fn mutants(ast: &AST) -> Vec<Mutant> {
+    let mut mutants = vec![];
+    for (index, node) in ast.nodes.iter().enumerate() {
+        if let Node::Cell(cell) = node {
+            for target in 0.. {
+                let mut mutated = ast.clone();
+                let description = match &mut mutated.nodes[index] {
+                    Node::Cell(cell) => mutate(&mut cell.expr, target, &mut 0),
+                    _ => unreachable!(),
+                };
+                match description {
+                    Some(description) => mutants.push(Mutant {
+                        cell: cell.name.clone(),
+                        description,
+                        ast: mutated,
+                    }),
+                    None => break,
+                }
+            }
+        }
+    }
+    mutants
+}
+
+/// Applies the mutation number `target`, counting the mutations of `expr`
+/// and its sub-expressions in pre-order. `None` if there are fewer.
+fn mutate(expr: &mut Expr, target: usize, seen: &mut usize) -> Option<String> {
+    if let Some((mutated, description)) = mutation(expr) {
+        if *seen == target {
+            *expr = mutated;
+            return Some(description);
+        }
+        *seen += 1;
+    }
+    for child in children_mut(expr) {
+        if let Some(description) = mutate(child, target, seen) {
+            return Some(description);
+        }
+    }
+    None
+}
+
+/// The mutation of `expr` itself, swapping an operator for a likely
+/// mistake or perturbing a constant
+fn mutation(expr: &Expr) -> Option<(Expr, String)> {
+    let (l, r) = match expr {
+        Expr::Add(l, r)
+        | Expr::Sub(l, r)
+        | Expr::Mul(l, r)
+        | Expr::Div(l, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r)
+        | Expr::Compare(_, l, r) => (l.clone(), r.clone()),
+        Expr::Atom(Atom::Number(x)) => {
+            let perturbed = if *x == 0.0 { 1.0 } else { x * 1.1 };
+            let description = format!("`{}` -> `{}`", x, perturbed);
+            return Some((Expr::Atom(Atom::Number(perturbed)), description));
+        }
+        _ => return None,
+    };
+    let mutation = match expr {
+        Expr::Add(..) => (Expr::Sub(l, r), "`+` -> `-`".to_string()),
+        Expr::Sub(..) => (Expr::Add(l, r), "`-` -> `+`".to_string()),
+        Expr::Mul(..) => (Expr::Div(l, r), "`*` -> `/`".to_string()),
+        Expr::Div(..) => (Expr::Mul(l, r), "`/` -> `*`".to_string()),
+        Expr::And(..) => (Expr::Or(l, r), "`&&` -> `||`".to_string()),
+        Expr::Or(..) => (Expr::And(l, r), "`||` -> `&&`".to_string()),
+        Expr::Compare(op, ..) => {
+            let swapped = match op {
+                Operator::Greater => Operator::GreaterEqual,
+                Operator::GreaterEqual => Operator::Greater,
+                Operator::Less => Operator::LessEqual,
+                Operator::LessEqual => Operator::Less,
+                Operator::Equals => Operator::NotEquals,
+                Operator::NotEquals => Operator::Equals,
+            };
+            let description = format!("`{}` -> `{}`", op, swapped);
+            (Expr::Compare(swapped, l, r), description)
+        }
+        _ => unreachable!(),
+    };
+    Some(mutation)
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub mutants: usize,
+    /// `(cell, description)` of the mutants no assertion caught
+    pub survived: Vec<(String, String)>,
+}
+
+/// Runs the assertions of every mutant against `permutations`. The model
+/// itself has to pass them.
+pub fn run(ast: &AST, permutations: &[Params]) -> Result<Report, anyhow::Error> {
+    if !ast.nodes.iter().any(|node| matches!(node, Node::Assert(_))) {
+        bail!("the model has no `assert`s to catch mutations with");
+    }
+    for params in permutations {
+        ast_interpreter::run(ast, &[], params)
+            .map_err(|e| e.context("the model fails its own assertions"))?;
+    }
+
+    let mutants = mutants(ast);
+    let survived = mutants
+        .par_iter()
+        .filter(|mutant| {
+            permutations
+                .iter()
+                .all(|params| ast_interpreter::run(&mutant.ast, &[], params).is_ok())
+        })
+        .map(|mutant| (mutant.cell.clone(), mutant.description.clone()))
+        .collect();
+    Ok(Report {
+        mutants: mutants.len(),
+        survived,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{ast_interpreter::Value, parser, scanner};
+
+    fn parse(code: &str) -> AST {
+        parser::parse(scanner::scan_spanned(code).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_mutants() {
+        let ast = parse("cell a: 1 + b * 2; cell b: if c > 0 ? c : 0;");
+        let mutants: Vec<_> = mutants(&ast)
+            .into_iter()
+            .map(|mutant| (mutant.cell, mutant.ast.to_string()))
+            .collect();
+        let expected = [
+            ("a", "cell a: 1 - b * 2;\ncell b: if c > 0 ? c : 0;\n"),
+            ("a", "cell a: 1.1 + b * 2;\ncell b: if c > 0 ? c : 0;\n"),
+            ("a", "cell a: 1 + b / 2;\ncell b: if c > 0 ? c : 0;\n"),
+            ("a", "cell a: 1 + b * 2.2;\ncell b: if c > 0 ? c : 0;\n"),
+            ("b", "cell a: 1 + b * 2;\ncell b: if c >= 0 ? c : 0;\n"),
+            ("b", "cell a: 1 + b * 2;\ncell b: if c > 1 ? c : 0;\n"),
+            ("b", "cell a: 1 + b * 2;\ncell b: if c > 0 ? c : 1;\n"),
+        ];
+        assert_eq!(
+            mutants,
+            expected.map(|(cell, ast)| (cell.to_string(), ast.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let ast =
+            parse("param x; cell fee: x * 2; cell total: fee + 1; assert total == 2 * x + 1;");
+        let permutations = vec![HashMap::from([("x".to_string(), Value::from(3f64))])];
+        let report = run(&ast, &permutations).unwrap();
+        assert_eq!(report.mutants, 4);
+        assert!(report.survived.is_empty(), "{:?}", report.survived);
+
+        // a loose assertion lets every mutant through
+        let ast = parse("param x; cell fee: x * 2; cell total: fee + 1; assert total > 0;");
+        let report = run(&ast, &permutations).unwrap();
+        assert_eq!(report.survived.len(), 4);
+
+        assert!(run(&parse("cell a: 1;"), &permutations).is_err());
+        let error = run(&parse("cell a: 1; assert a > 1;"), &permutations).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "the model fails its own assertions: assertion `a > 1` failed"
+        );
+    }
+}
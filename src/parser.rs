@@ -1,41 +1,99 @@
 use std::iter::Peekable;
 
-use anyhow::bail;
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    scanner::Token,
+    sema::HARD_MAX_DEPTH,
+};
 
-use crate::scanner::Token;
-
-#[derive(PartialEq, Debug, Default)]
+#[derive(PartialEq, Debug, Default, Clone, serde::Serialize)]
 pub struct AST {
     pub nodes: Vec<Node>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Node {
     Param(Param),
     Cell(Cell),
+    Directive(Directive),
+    Import(Import),
+    Assert(Assert),
+}
+
+/// `assert total >= 0 : "total must be non-negative";`, checked once the
+/// queried cells are computed
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+pub struct Assert {
+    pub expr: Expr,
+    pub message: Option<String>,
+    pub span: Span,
+}
+
+/// `import "costs.cell";`, resolved relative to the importing file. With
+/// `as costs` the cells and params of the file are referenced as
+/// `costs.<name>`.
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+pub struct Import {
+    pub path: String,
+    pub alias: Option<String>,
+    pub span: Span,
+}
+
+/// A model-level setting, e.g. `@eq-tolerance(1e-9)`
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+pub struct Directive {
+    pub name: String,
+    pub arguments: Vec<Atom>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
 pub struct Param {
     pub name: String,
+    /// `string` in `param provider: string;`, the kind of value the param
+    /// takes. Any kind when missing.
+    #[serde(rename = "type")]
+    pub ty: Option<ParamType>,
+    /// the `## ...` lines right before the param
+    pub doc: Option<String>,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamType {
+    Number,
+    String,
+    Bool,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
 pub struct Cell {
     pub name: String,
+    /// `t` in `cell balance[t]: ...`, the cell has one value per period
+    pub index: Option<String>,
     pub expr: Expr,
+    /// `output cell ...`, computed when no cells are queried
+    pub output: bool,
+    /// the `## ...` lines right before the cell
+    pub doc: Option<String>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Operator {
     Equals,
+    NotEquals,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Expr {
     Atom(Atom),
     Add(Box<Expr>, Box<Expr>),
@@ -43,223 +101,714 @@ pub enum Expr {
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Compare(Operator, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    List(Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
     Condition {
-        lhs: Box<Expr>,
-        rhs: Box<Expr>,
-        op: Operator,
+        cond: Box<Expr>,
         true_branch: Box<Expr>,
         false_branch: Box<Expr>,
     },
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Atom {
     Number(f64),
+    String(String),
+    Bool(bool),
     Ident(String),
     Call { name: String, arguments: Vec<Expr> },
 }
 
 impl<'a> Token<'a> {
-    fn is_operator(&self) -> bool {
+    /// Binding power of a binary operator, higher binds tighter. `None` if
+    /// the token is not a binary operator.
+    fn precedence(&self) -> Option<u8> {
         match self {
-            Token::Mul | Token::Add | Token::Sub | Token::Div | Token::Mod => true,
-            _ => false,
+            Token::Or => Some(1),
+            Token::And => Some(2),
+            Token::Equal
+            | Token::NotEqual
+            | Token::Greater
+            | Token::GreaterEqual
+            | Token::Less
+            | Token::LessEqual => Some(3),
+            Token::Add | Token::Sub => Some(4),
+            Token::Mul | Token::Div | Token::Mod => Some(5),
+            Token::Pow => Some(POW_PRECEDENCE),
+            _ => None,
         }
     }
 }
 
-fn parse_atom<'a, T: Iterator<Item = Token<'a>>>(
-    tokens: &mut Peekable<T>,
-) -> Result<Atom, anyhow::Error> {
-    let token = tokens
-        .next()
-        .ok_or_else(|| anyhow::Error::msg("[8] expected a token"))?;
-    let next_token = tokens.peek();
+const POW_PRECEDENCE: u8 = 6;
+
+/// Token stream that remembers the location of the last token it handed
+/// out, so errors can point at it.
+struct Tokens<'a> {
+    tokens: Peekable<std::vec::IntoIter<(Token<'a>, Span)>>,
+    /// span of the last token returned by `next` or `peek`
+    span: Span,
+    /// end of the last token returned by `next`
+    consumed_end: usize,
+    input_end: usize,
+    /// `##` lines skipped since the last call to `take_doc`, with where
+    /// they start
+    doc: Vec<(&'a str, usize)>,
+    /// how deeply the expression being parsed nests, see `nested`
+    depth: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(tokens: Vec<(Token<'a>, Span)>) -> Self {
+        let input_end = tokens.last().map(|(_, span)| span.end).unwrap_or(0);
+        Self {
+            tokens: tokens.into_iter().peekable(),
+            span: 0..0,
+            consumed_end: 0,
+            input_end,
+            doc: vec![],
+            depth: 0,
+        }
+    }
+
+    /// Doc comments are only meaningful between nodes, the parser never
+    /// sees them as tokens
+    fn skip_doc(&mut self) {
+        while let Some((Token::Doc(text), span)) = self.tokens.peek() {
+            self.doc.push((text, span.start));
+            self.tokens.next();
+        }
+    }
+
+    /// The lines of the doc comments collected since the last call which
+    /// start after `after`
+    fn take_doc(&mut self, after: usize) -> Option<String> {
+        let lines: Vec<_> = std::mem::take(&mut self.doc)
+            .into_iter()
+            .filter(|(_, start)| *start >= after)
+            .map(|(text, _)| text)
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.skip_doc();
+        match self.tokens.next() {
+            Some((token, span)) => {
+                self.consumed_end = span.end;
+                self.span = span;
+                Some(token)
+            }
+            None => {
+                self.span = self.input_end..self.input_end;
+                None
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token<'a>> {
+        self.skip_doc();
+        match self.tokens.peek() {
+            Some((token, span)) => {
+                self.span = span.clone();
+                Some(token)
+            }
+            None => {
+                self.span = self.input_end..self.input_end;
+                None
+            }
+        }
+    }
+
+    /// An error pointing at the last token returned by `next` or `peek`
+    fn error(&self, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(message).with_span(self.span.clone())
+    }
+
+    /// An error if an expression `levels` deeper than the one being parsed
+    /// nests past `HARD_MAX_DEPTH`
+    fn check_depth(&self, levels: usize) -> Result<(), Diagnostic> {
+        if self.depth + levels <= HARD_MAX_DEPTH {
+            return Ok(());
+        }
+        Err(self
+            .error(format!(
+                "the expression is nested more than {} levels deep",
+                HARD_MAX_DEPTH
+            ))
+            .with_hint("move sub-expressions into their own cells"))
+    }
+
+    /// Parses a sub-expression with `parse`, one level deeper. Too deep
+    /// an expression is an error instead of overflowing the stack.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        self.check_depth(1)?;
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+fn found(token: Option<&Token>) -> String {
     match token {
-        Token::Ident(x) if matches!(next_token, Some(Token::ParOpen)) => {
-            let fn_name = x;
+        Some(token) => token.to_string(),
+        None => "end of input".to_string(),
+    }
+}
+
+/// Comma separated expressions up to and including `close`. `what` names
+/// the list in errors, e.g. "the arguments of `max`"
+fn parse_expr_list(
+    tokens: &mut Tokens,
+    close: Token,
+    what: &str,
+) -> Result<Vec<Expr>, anyhow::Error> {
+    let mut items = vec![];
+    if tokens.peek() == Some(&close) {
+        tokens.next();
+        return Ok(items);
+    }
+    loop {
+        items.push(parse_expr(tokens)?);
+        match tokens.next() {
+            Some(x) if x == close => break,
+            Some(Token::Comma) => continue,
+            x => {
+                return Err(tokens
+                    .error(format!(
+                        "expected `,` or {} in {}, found {}",
+                        close,
+                        what,
+                        found(x.as_ref())
+                    ))
+                    .into())
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn parse_atom(tokens: &mut Tokens) -> Result<Atom, anyhow::Error> {
+    match tokens.next() {
+        Some(Token::Ident(x)) if matches!(tokens.peek(), Some(Token::ParOpen)) => {
             // skip para
             tokens.next();
-            let mut args = vec![];
-            while let Ok(expr) = parse_expr(tokens) {
-                args.push(expr);
-                let next_token = tokens.peek();
-                match next_token {
-                    Some(Token::ParClose) => {
-                        tokens.next();
-                        break;
-                    }
-                    Some(Token::Comma) => {
-                        tokens.next();
-                        continue;
-                    }
-                    x => bail!("invalid token {:?}", x),
-                }
-            }
+            let arguments = parse_expr_list(
+                tokens,
+                Token::ParClose,
+                &format!("the arguments of `{}`", x),
+            )?;
             Ok(Atom::Call {
-                name: fn_name.to_string(),
-                arguments: args,
+                name: x.to_string(),
+                arguments,
             })
         }
-        Token::Ident(x) => Ok(Atom::Ident(x.to_string())),
-        Token::Number(x) => {
-            let number: f64 = x.parse()?;
-            Ok(Atom::Number(number))
-        }
-        x => bail!("[7] unexpected token {:?}", x),
+        Some(Token::Ident(x)) => Ok(Atom::Ident(x.to_string())),
+        Some(Token::String(x)) => Ok(Atom::String(x.to_string())),
+        Some(Token::True) => Ok(Atom::Bool(true)),
+        Some(Token::False) => Ok(Atom::Bool(false)),
+        Some(Token::Number(x)) => match x.parse() {
+            Ok(number) => Ok(Atom::Number(number)),
+            Err(_) => Err(tokens.error(format!("invalid number `{}`", x)).into()),
+        },
+        x => Err(tokens
+            .error(format!(
+                "expected an expression, found {}",
+                found(x.as_ref())
+            ))
+            .into()),
     }
 }
 
-fn parse_cond<'a, T: Iterator<Item = Token<'a>>>(
-    tokens: &mut Peekable<T>,
-) -> Result<Expr, anyhow::Error> {
-    // skip if
+/// `if c1 ? e1 elif c2 ? e2 : e3` is sugar for `if c1 ? e1 : if c2 ? e2 : e3`
+fn parse_cond(tokens: &mut Tokens) -> Result<Expr, anyhow::Error> {
+    // skip if or elif
     tokens.next();
-    // expr
-    let lhs = Box::new(parse_expr(tokens)?);
-    // op
-    let op = match tokens.next() {
-        Some(Token::Greater) => Operator::Greater,
-        Some(Token::GreaterEqual) => Operator::GreaterEqual,
-        Some(Token::Less) => Operator::Less,
-        Some(Token::LessEqual) => Operator::LessEqual,
-        Some(Token::Equal) => Operator::Equals,
-        x => bail!("unexpected token {:?}", x),
-    };
-    // expr
-    let rhs = Box::new(parse_expr(tokens)?);
+    let cond = Box::new(parse_expr(tokens)?);
     let token = tokens.next();
     if !matches!(token, Some(Token::QMark)) {
-        bail!("expected ? found {:?}", token);
+        return Err(tokens
+            .error(format!(
+                "expected `?` after the condition, found {}",
+                found(token.as_ref())
+            ))
+            .with_hint("conditions look like `if a > b ? x : y`")
+            .into());
     }
     let true_branch = Box::new(parse_expr(tokens)?);
+    if let Some(Token::Elif) = tokens.peek() {
+        return Ok(Expr::Condition {
+            cond,
+            true_branch,
+            false_branch: Box::new(tokens.nested(parse_cond)?),
+        });
+    }
     let token = tokens.next();
     if !matches!(token, Some(Token::Colon)) {
-        bail!("expected : found {:?}", token);
+        return Err(tokens
+            .error(format!(
+                "expected `elif` or `:` before the false branch, found {}",
+                found(token.as_ref())
+            ))
+            .with_hint("conditions look like `if a > b ? x : y`")
+            .into());
     }
     let false_branch = Box::new(parse_expr(tokens)?);
-    return Ok(Expr::Condition {
-        lhs,
-        rhs,
-        op,
+    Ok(Expr::Condition {
+        cond,
         true_branch,
         false_branch,
-    });
+    })
 }
 
-fn parse_expr<'a, T: Iterator<Item = Token<'a>>>(
-    tokens: &mut Peekable<T>,
-) -> Result<Expr, anyhow::Error> {
-    let first = tokens
-        .peek()
-        .ok_or_else(|| anyhow::Error::msg("[6] expected a token"))?;
-
-    let lhs_expr = {
-        match first {
-            Token::ParOpen => {
-                tokens.next();
-                let expr = parse_expr(tokens)?;
-                match tokens.next() {
-                    Some(Token::ParClose) => expr,
-                    x => bail!("[5] unexpected token {:?}", x),
+fn parse_primary(tokens: &mut Tokens) -> Result<Expr, anyhow::Error> {
+    let mut expr = match tokens.peek() {
+        Some(Token::ParOpen) => {
+            tokens.next();
+            let expr = parse_expr(tokens)?;
+            match tokens.next() {
+                Some(Token::ParClose) => expr,
+                x => {
+                    return Err(tokens
+                        .error(format!("expected `)`, found {}", found(x.as_ref())))
+                        .into())
                 }
             }
-            Token::If => {
-                let cond = parse_cond(tokens)?;
-                cond
-            }
-            _ => {
-                let atom = parse_atom(tokens)?;
-                Expr::Atom(atom)
-            }
         }
+        Some(Token::BracketOpen) => {
+            tokens.next();
+            Expr::List(parse_expr_list(tokens, Token::BracketClose, "the list")?)
+        }
+        Some(Token::If) => return parse_cond(tokens),
+        Some(Token::Not) => {
+            tokens.next();
+            return Ok(Expr::Not(Box::new(tokens.nested(parse_primary)?)));
+        }
+        Some(Token::Sub) => {
+            tokens.next();
+            // `**` binds tighter, `-b ** 2` is `-(b ** 2)` like in math
+            let operand = tokens.nested(|tokens| parse_binary(tokens, POW_PRECEDENCE))?;
+            return Ok(Expr::Neg(Box::new(operand)));
+        }
+        _ => Expr::Atom(parse_atom(tokens)?),
     };
 
-    if let Some(next) = tokens.peek() {
-        if next.is_operator() {
-            // SAFETY: we already checked with `peek`
-            let next = tokens.next().unwrap();
-            let rhs_expr = parse_expr(tokens)?;
-            Ok(match next {
-                Token::Mul => Expr::Mul(Box::new(lhs_expr), Box::new(rhs_expr)),
-                Token::Add => Expr::Add(Box::new(lhs_expr), Box::new(rhs_expr)),
-                Token::Sub => Expr::Sub(Box::new(lhs_expr), Box::new(rhs_expr)),
-                Token::Div => Expr::Div(Box::new(lhs_expr), Box::new(rhs_expr)),
-                Token::Mod => Expr::Mod(Box::new(lhs_expr), Box::new(rhs_expr)),
-                _ => bail!("unreachable!"),
-            })
-        } else {
-            Ok(lhs_expr)
+    // indexing binds tighter than any prefix or binary operator
+    let mut indexes = 0;
+    while let Some(Token::BracketOpen) = tokens.peek() {
+        tokens.next();
+        // `x[0][1]` nests `x[0]` one level deeper
+        indexes += 1;
+        tokens.check_depth(indexes)?;
+        let index = parse_expr(tokens)?;
+        match tokens.next() {
+            Some(Token::BracketClose) => {}
+            x => {
+                return Err(tokens
+                    .error(format!("expected `]`, found {}", found(x.as_ref())))
+                    .into())
+            }
+        }
+        expr = Expr::Index(Box::new(expr), Box::new(index));
+    }
+    Ok(expr)
+}
+
+fn compare(op: Operator, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Compare(op, Box::new(lhs), Box::new(rhs))
+}
+
+/// precedence climbing: operators binding at least as tight as
+/// `min_precedence` are folded into a left-associative chain.
+fn parse_binary(tokens: &mut Tokens, min_precedence: u8) -> Result<Expr, anyhow::Error> {
+    let mut lhs_expr = parse_primary(tokens)?;
+
+    // `a + b + c` nests `a + b` one level deeper
+    let mut operators = 0;
+    while let Some(precedence) = tokens.peek().and_then(Token::precedence) {
+        if precedence < min_precedence {
+            break;
         }
-    } else {
-        Ok(lhs_expr)
+        // SAFETY: we already checked with `peek`
+        let next = tokens.next().unwrap();
+        operators += 1;
+        tokens.check_depth(operators)?;
+        // `**` is right-associative: 2 ** 3 ** 2 == 2 ** (3 ** 2)
+        let rhs_precedence = match next {
+            Token::Pow => precedence,
+            _ => precedence + 1,
+        };
+        let rhs_expr = tokens.nested(|tokens| parse_binary(tokens, rhs_precedence))?;
+        lhs_expr = match next {
+            Token::Mul => Expr::Mul(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Add => Expr::Add(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Sub => Expr::Sub(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Div => Expr::Div(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Mod => Expr::Mod(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Pow => Expr::Pow(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::And => Expr::And(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Or => Expr::Or(Box::new(lhs_expr), Box::new(rhs_expr)),
+            Token::Equal => compare(Operator::Equals, lhs_expr, rhs_expr),
+            Token::NotEqual => compare(Operator::NotEquals, lhs_expr, rhs_expr),
+            Token::Greater => compare(Operator::Greater, lhs_expr, rhs_expr),
+            Token::GreaterEqual => compare(Operator::GreaterEqual, lhs_expr, rhs_expr),
+            Token::Less => compare(Operator::Less, lhs_expr, rhs_expr),
+            Token::LessEqual => compare(Operator::LessEqual, lhs_expr, rhs_expr),
+            _ => unreachable!(),
+        };
     }
+
+    Ok(lhs_expr)
+}
+
+fn parse_expr(tokens: &mut Tokens) -> Result<Expr, anyhow::Error> {
+    tokens.nested(|tokens| parse_binary(tokens, 0))
 }
 
-fn parse_cell<'a, T: Iterator<Item = Token<'a>>>(
-    tokens: &mut Peekable<T>,
-) -> Result<Cell, anyhow::Error> {
-    let name = match (tokens.next(), tokens.next()) {
-        (Some(Token::Ident(name)), Some(Token::Colon)) => name,
-        x => bail!("[4] unexpected token: {:?}", x),
+fn parse_cell(tokens: &mut Tokens, start: usize) -> Result<Cell, anyhow::Error> {
+    let name = match tokens.next() {
+        Some(Token::Ident(name)) => name,
+        x => {
+            return Err(tokens
+                .error(format!("expected a cell name, found {}", found(x.as_ref())))
+                .into())
+        }
     };
+    let mut index = None;
+    if tokens.peek() == Some(&Token::BracketOpen) {
+        tokens.next();
+        match (tokens.next(), tokens.next()) {
+            (Some(Token::Ident(x)), Some(Token::BracketClose)) => index = Some(x.to_string()),
+            _ => {
+                return Err(tokens
+                    .error("expected the name of the period, e.g. `[t]`")
+                    .with_hint(
+                        "time-indexed cells look like `cell balance[t]: balance[t - 1] * 2;`",
+                    )
+                    .into())
+            }
+        }
+    }
+    let token = tokens.next();
+    if !matches!(token, Some(Token::Colon)) {
+        return Err(tokens
+            .error(format!(
+                "expected `:` after the cell name, found {}",
+                found(token.as_ref())
+            ))
+            .with_hint("cells look like `cell total: a + b;`")
+            .into());
+    }
     let expr = parse_expr(tokens)?;
     match tokens.next() {
         Some(Token::SemiColon) => Ok(Cell {
             name: name.to_string(),
+            index,
             expr,
+            output: false,
+            doc: None,
+            span: start..tokens.consumed_end,
         }),
-        x => bail!("[3] unexpected token: {:?}", x),
+        x => Err(tokens
+            .error(format!(
+                "expected `;` after the cell expression, found {}",
+                found(x.as_ref())
+            ))
+            .with_hint("every cell ends with `;`")
+            .into()),
     }
 }
 
-fn parse_param<'a, T: Iterator<Item = Token<'a>>>(
-    tokens: &mut Peekable<T>,
-) -> Result<Param, anyhow::Error> {
-    match (tokens.next(), tokens.next()) {
-        (Some(Token::Ident(name)), Some(Token::SemiColon)) => Ok(Param {
+fn parse_assert(tokens: &mut Tokens, start: usize) -> Result<Assert, anyhow::Error> {
+    let expr = parse_expr(tokens)?;
+    let mut message = None;
+    if tokens.peek() == Some(&Token::Colon) {
+        tokens.next();
+        match tokens.next() {
+            Some(Token::String(x)) => message = Some(x.to_string()),
+            x => {
+                return Err(tokens
+                    .error(format!(
+                        "expected the message of the assertion, found {}",
+                        found(x.as_ref())
+                    ))
+                    .with_hint("assertions look like `assert total >= 0 : \"negative total\";`")
+                    .into())
+            }
+        }
+    }
+    match tokens.next() {
+        Some(Token::SemiColon) => Ok(Assert {
+            expr,
+            message,
+            span: start..tokens.consumed_end,
+        }),
+        x => Err(tokens
+            .error(format!(
+                "expected `;` after the assertion, found {}",
+                found(x.as_ref())
+            ))
+            .into()),
+    }
+}
+
+fn parse_param(tokens: &mut Tokens, start: usize) -> Result<Param, anyhow::Error> {
+    let name = match tokens.next() {
+        Some(Token::Ident(name)) => name,
+        x => {
+            return Err(tokens
+                .error(format!(
+                    "expected a param name, found {}",
+                    found(x.as_ref())
+                ))
+                .into())
+        }
+    };
+    let mut ty = None;
+    if let Some(Token::Colon) = tokens.peek() {
+        tokens.next();
+        ty = match tokens.next() {
+            Some(Token::Ident("number")) => Some(ParamType::Number),
+            Some(Token::Ident("string")) => Some(ParamType::String),
+            Some(Token::Ident("bool")) => Some(ParamType::Bool),
+            x => {
+                return Err(tokens
+                    .error(format!(
+                        "expected a param type, found {}",
+                        found(x.as_ref())
+                    ))
+                    .with_hint("the types are `number`, `string` and `bool`")
+                    .into())
+            }
+        };
+    }
+    match tokens.next() {
+        Some(Token::SemiColon) => Ok(Param {
             name: name.to_string(),
+            ty,
+            doc: None,
+            span: start..tokens.consumed_end,
+        }),
+        x => Err(tokens
+            .error(format!(
+                "expected `;` after the param name, found {}",
+                found(x.as_ref())
+            ))
+            .with_hint("params look like `param price;`")
+            .into()),
+    }
+}
+
+fn parse_import(tokens: &mut Tokens, start: usize) -> Result<Import, anyhow::Error> {
+    let path = match tokens.next() {
+        Some(Token::String(path)) => path,
+        x => {
+            return Err(tokens
+                .error(format!(
+                    "expected the path of the imported file, found {}",
+                    found(x.as_ref())
+                ))
+                .with_hint("imports look like `import \"costs.cell\";`")
+                .into())
+        }
+    };
+    // `as` is only a keyword here, it stays a valid cell name
+    let mut alias = None;
+    if tokens.peek() == Some(&Token::Ident("as")) {
+        tokens.next();
+        match tokens.next() {
+            Some(Token::Ident(name)) if !name.contains('.') => alias = Some(name.to_string()),
+            x => {
+                return Err(tokens
+                    .error(format!(
+                        "expected the name of the namespace, found {}",
+                        found(x.as_ref())
+                    ))
+                    .with_hint("namespaced imports look like `import \"costs.cell\" as costs;`")
+                    .into())
+            }
+        }
+    }
+    match tokens.next() {
+        Some(Token::SemiColon) => Ok(Import {
+            path: path.to_string(),
+            alias,
+            span: start..tokens.consumed_end,
         }),
-        x => bail!("[2] unexpected token: {:?}", x),
+        x => Err(tokens
+            .error(format!(
+                "expected `;` after the import, found {}",
+                found(x.as_ref())
+            ))
+            .into()),
+    }
+}
+
+fn parse_directive(
+    name: &str,
+    tokens: &mut Tokens,
+    start: usize,
+) -> Result<Directive, anyhow::Error> {
+    let mut arguments = vec![];
+    if matches!(tokens.peek(), Some(Token::ParOpen)) {
+        tokens.next();
+        while !matches!(tokens.peek(), Some(Token::ParClose)) {
+            // `-` is scanned on its own, `@offset(-1)` is still a number
+            let negative = tokens.peek() == Some(&Token::Sub);
+            if negative {
+                tokens.next();
+            }
+            match parse_atom(tokens)? {
+                Atom::Number(x) if negative => arguments.push(Atom::Number(-x)),
+                _ if negative => {
+                    return Err(tokens
+                        .error(format!("expected a number after `-` in `@{}`", name))
+                        .into())
+                }
+                atom => arguments.push(atom),
+            }
+            match tokens.peek() {
+                Some(Token::ParClose) => {}
+                Some(Token::Comma) => {
+                    tokens.next();
+                }
+                x => {
+                    let message = format!(
+                        "expected `,` or `)` in the arguments of `@{}`, found {}",
+                        name,
+                        found(x)
+                    );
+                    return Err(tokens.error(message).into());
+                }
+            }
+        }
+        tokens.next();
     }
+    Ok(Directive {
+        name: name.to_string(),
+        arguments,
+        span: start..tokens.consumed_end,
+    })
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<AST, anyhow::Error> {
+pub fn parse(tokens: Vec<(Token, Span)>) -> Result<AST, anyhow::Error> {
+    #[cfg(feature = "telemetry")]
+    let started = std::time::Instant::now();
     let mut ast = AST::default();
-    let mut tokens = tokens.into_iter().peekable();
+    let mut tokens = Tokens::new(tokens);
 
+    // end of the previous node, docs inside it are dropped
+    let mut previous_end = 0;
     while let Some(token) = tokens.next() {
+        let start = tokens.span.start;
+        let doc = tokens.take_doc(previous_end);
         match token {
             Token::Param => {
-                ast.nodes.push(Node::Param(parse_param(&mut tokens)?));
+                let param = parse_param(&mut tokens, start)?;
+                ast.nodes.push(Node::Param(Param { doc, ..param }));
             }
             Token::Cell => {
-                ast.nodes.push(Node::Cell(parse_cell(&mut tokens)?));
+                let cell = parse_cell(&mut tokens, start)?;
+                ast.nodes.push(Node::Cell(Cell { doc, ..cell }));
+            }
+            // `assert` is only a keyword at the start of a statement
+            Token::Ident("assert") => {
+                ast.nodes
+                    .push(Node::Assert(parse_assert(&mut tokens, start)?));
+            }
+            // `output` is only a keyword in front of `cell`
+            Token::Ident("output") if tokens.peek() == Some(&Token::Cell) => {
+                tokens.next();
+                let cell = parse_cell(&mut tokens, start)?;
+                ast.nodes.push(Node::Cell(Cell {
+                    doc,
+                    output: true,
+                    ..cell
+                }));
+            }
+            Token::Import => {
+                ast.nodes
+                    .push(Node::Import(parse_import(&mut tokens, start)?));
+            }
+            Token::Directive(name) => {
+                ast.nodes
+                    .push(Node::Directive(parse_directive(name, &mut tokens, start)?));
+            }
+            x => {
+                return Err(tokens
+                    .error(format!(
+                        "expected `param`, `cell`, `import` or a directive, found {}",
+                        x
+                    ))
+                    .into())
             }
-            x => bail!("[1] unexpected token {:?}, expected 'param' or 'cell'", x),
         }
+        previous_end = tokens.consumed_end;
     }
 
+    #[cfg(feature = "telemetry")]
+    crate::telemetry::emit(|| crate::telemetry::Event::Parsed {
+        nodes: ast.nodes.len(),
+        duration: started.elapsed(),
+    });
     Ok(ast)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner;
+    use crate::{diagnostic::Diagnostic, scanner};
     fn parse(input: &str) -> String {
-        let tokens = scanner::scan(input).unwrap();
+        let tokens = scanner::scan_spanned(input).unwrap();
         format!("{:?}", super::parse(tokens).unwrap())
     }
 
+    #[track_caller]
+    fn parse_error(input: &str) -> Diagnostic {
+        let tokens = scanner::scan_spanned(input).unwrap();
+        let error = super::parse(tokens).unwrap_err();
+        error.downcast_ref::<Diagnostic>().unwrap().clone()
+    }
+
     #[test]
     fn test_param() {
         assert_eq!(
             parse("param test;"),
-            "AST { nodes: [Param(Param { name: \"test\" })] }"
+            "AST { nodes: [Param(Param { name: \"test\", ty: None, doc: None, span: 0..11 })] }"
         );
         assert_eq!(
             parse("param test; param test2;"),
-            "AST { nodes: [Param(Param { name: \"test\" }), Param(Param { name: \"test2\" })] }"
+            "AST { nodes: [Param(Param { name: \"test\", ty: None, doc: None, span: 0..11 }), Param(Param { name: \"test2\", ty: None, doc: None, span: 12..24 })] }"
+        );
+        assert_eq!(
+            parse("param provider: string;"),
+            "AST { nodes: [Param(Param { name: \"provider\", ty: Some(String), doc: None, span: 0..23 })] }"
+        );
+        assert_eq!(
+            parse_error("param a: text;").message,
+            "expected a param type, found `text`"
+        );
+    }
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(
+            parse("@eq-tolerance(0.5)"),
+            "AST { nodes: [Directive(Directive { name: \"eq-tolerance\", arguments: [Number(0.5)], span: 0..18 })] }"
+        );
+        assert_eq!(
+            parse("@offset(-1)"),
+            "AST { nodes: [Directive(Directive { name: \"offset\", arguments: [Number(-1.0)], span: 0..11 })] }"
         );
     }
 
@@ -267,15 +816,15 @@ mod tests {
     fn test_func() {
         assert_eq!(
             parse("cell test: random();"),
-            "AST { nodes: [Cell(Cell { name: \"test\", expr: Atom(Call { name: \"random\", arguments: [] }) })] }"
+            "AST { nodes: [Cell(Cell { name: \"test\", index: None, expr: Atom(Call { name: \"random\", arguments: [] }), output: false, doc: None, span: 0..20 })] }"
         );
         assert_eq!(
             parse("cell test: random(1);"),
-            "AST { nodes: [Cell(Cell { name: \"test\", expr: Atom(Call { name: \"random\", arguments: [Atom(Number(1.0))] }) })] }"
+            "AST { nodes: [Cell(Cell { name: \"test\", index: None, expr: Atom(Call { name: \"random\", arguments: [Atom(Number(1.0))] }), output: false, doc: None, span: 0..21 })] }"
         );
         assert_eq!(
             parse("cell test: random(1, 2, 3) + 1;"),
-            "AST { nodes: [Cell(Cell { name: \"test\", expr: Add(Atom(Call { name: \"random\", arguments: [Atom(Number(1.0)), Atom(Number(2.0)), Atom(Number(3.0))] }), Atom(Number(1.0))) })] }"
+            "AST { nodes: [Cell(Cell { name: \"test\", index: None, expr: Add(Atom(Call { name: \"random\", arguments: [Atom(Number(1.0)), Atom(Number(2.0)), Atom(Number(3.0))] }), Atom(Number(1.0))), output: false, doc: None, span: 0..31 })] }"
         );
     }
 
@@ -283,7 +832,7 @@ mod tests {
     fn test_cell() {
         assert_eq!(
             parse(r#"cell test2: 1;"#),
-            "AST { nodes: [Cell(Cell { name: \"test2\", expr: Atom(Number(1.0)) })] }"
+            "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Atom(Number(1.0)), output: false, doc: None, span: 0..14 })] }"
         );
         assert_eq!(
             parse(
@@ -292,12 +841,200 @@ mod tests {
         cell test2: 1 + 2;
         "#
             ),
-            "AST { nodes: [Cell(Cell { name: \"test\", expr: Atom(Number(1.0)) }), Cell(Cell { name: \"test2\", expr: Add(Atom(Number(1.0)), Atom(Number(2.0))) })] }"
+            "AST { nodes: [Cell(Cell { name: \"test\", index: None, expr: Atom(Number(1.0)), output: false, doc: None, span: 9..22 }), Cell(Cell { name: \"test2\", index: None, expr: Add(Atom(Number(1.0)), Atom(Number(2.0))), output: false, doc: None, span: 31..49 })] }"
+        );
+        assert_eq!(parse(r#"cell test2: (1 + 2) + 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Add(Add(Atom(Number(1.0)), Atom(Number(2.0))), Atom(Number(3.0))), output: false, doc: None, span: 0..24 })] }");
+        assert_eq!(parse(r#"cell test2: (1 / abc) + 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Add(Div(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))), output: false, doc: None, span: 0..26 })] }");
+        assert_eq!(parse(r#"cell test2: (1 + abc) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Sub(Add(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))), output: false, doc: None, span: 0..26 })] }");
+        assert_eq!(parse(r#"cell test2: (1 * abc) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Sub(Mul(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))), output: false, doc: None, span: 0..26 })] }");
+        assert_eq!(parse(r#"cell test2: (-1 * (abc)) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", index: None, expr: Sub(Mul(Neg(Atom(Number(1.0))), Atom(Ident(\"abc\"))), Atom(Number(3.0))), output: false, doc: None, span: 0..29 })] }");
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(parse(r#"cell a: 1 + 2 * 3;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Add(Atom(Number(1.0)), Mul(Atom(Number(2.0)), Atom(Number(3.0)))), output: false, doc: None, span: 0..18 })] }");
+        assert_eq!(parse(r#"cell a: 1 * 2 + 3;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Add(Mul(Atom(Number(1.0)), Atom(Number(2.0))), Atom(Number(3.0))), output: false, doc: None, span: 0..18 })] }");
+        assert_eq!(parse(r#"cell a: 1 - 2 - 3;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Sub(Sub(Atom(Number(1.0)), Atom(Number(2.0))), Atom(Number(3.0))), output: false, doc: None, span: 0..18 })] }");
+        assert_eq!(parse(r#"cell a: 8 / 4 % 3;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Mod(Div(Atom(Number(8.0)), Atom(Number(4.0))), Atom(Number(3.0))), output: false, doc: None, span: 0..18 })] }");
+        assert_eq!(parse(r#"cell a: 1 - (2 - 3);"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Sub(Atom(Number(1.0)), Sub(Atom(Number(2.0)), Atom(Number(3.0)))), output: false, doc: None, span: 0..20 })] }");
+        assert_eq!(parse(r#"cell a: 2 * 3 ** 4 ** 5;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Mul(Atom(Number(2.0)), Pow(Atom(Number(3.0)), Pow(Atom(Number(4.0)), Atom(Number(5.0))))), output: false, doc: None, span: 0..24 })] }");
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(parse(r#"cell a: if p == "gcp" ? 1 : 2;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Condition { cond: Compare(Equals, Atom(Ident(\"p\")), Atom(String(\"gcp\"))), true_branch: Atom(Number(1.0)), false_branch: Atom(Number(2.0)) }, output: false, doc: None, span: 0..30 })] }");
+    }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(parse(r#"cell a: if a > 1 && b < 2 || !c ? x : y;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Condition { cond: Or(And(Compare(Greater, Atom(Ident(\"a\")), Atom(Number(1.0))), Compare(Less, Atom(Ident(\"b\")), Atom(Number(2.0)))), Not(Atom(Ident(\"c\")))), true_branch: Atom(Ident(\"x\")), false_branch: Atom(Ident(\"y\")) }, output: false, doc: None, span: 0..40 })] }");
+        assert_eq!(parse(r#"cell a: true && !(1 + 1 == 2);"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: And(Atom(Bool(true)), Not(Compare(Equals, Add(Atom(Number(1.0)), Atom(Number(1.0))), Atom(Number(2.0))))), output: false, doc: None, span: 0..30 })] }");
+    }
+
+    #[test]
+    fn test_not_equal() {
+        assert_eq!(parse(r#"cell a: 1 + 1 != 2;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Compare(NotEquals, Add(Atom(Number(1.0)), Atom(Number(1.0))), Atom(Number(2.0))), output: false, doc: None, span: 0..19 })] }");
+    }
+
+    #[test]
+    fn test_neg() {
+        assert_eq!(parse(r#"cell a: -abc * 2 - -(b);"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Sub(Mul(Neg(Atom(Ident(\"abc\"))), Atom(Number(2.0))), Neg(Atom(Ident(\"b\")))), output: false, doc: None, span: 0..24 })] }");
+
+        assert_eq!(parse("cell a: 5-1;"), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Sub(Atom(Number(5.0)), Atom(Number(1.0))), output: false, doc: None, span: 0..12 })] }");
+        assert_eq!(parse("cell a: b-1;"), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Sub(Atom(Ident(\"b\")), Atom(Number(1.0))), output: false, doc: None, span: 0..12 })] }");
+        assert_eq!(parse("cell x[t]: x[t-1];"), "AST { nodes: [Cell(Cell { name: \"x\", index: Some(\"t\"), expr: Index(Atom(Ident(\"x\")), Sub(Atom(Ident(\"t\")), Atom(Number(1.0)))), output: false, doc: None, span: 0..18 })] }");
+    }
+
+    #[test]
+    fn test_nesting() {
+        let nested = format!("cell a: {}1{};", "(".repeat(3000), ")".repeat(3000));
+        for code in [
+            nested,
+            format!("cell a: {}1;", "-".repeat(3000)),
+            format!("cell a: 1{};", " + 1".repeat(3000)),
+            format!("cell a: 1{};", " ** 1".repeat(3000)),
+            format!("cell a: x{};", "[0]".repeat(3000)),
+        ] {
+            assert_eq!(
+                parse_error(&code).message,
+                "the expression is nested more than 128 levels deep"
+            );
+        }
+        assert!(parse(&format!("cell a: 1{};", " + 1".repeat(100))).starts_with("AST"));
+    }
+
+    #[test]
+    fn test_neg_pow() {
+        assert_eq!(parse("cell a: -b ** 2 * 3;"), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Mul(Neg(Pow(Atom(Ident(\"b\")), Atom(Number(2.0)))), Atom(Number(3.0))), output: false, doc: None, span: 0..20 })] }");
+        assert_eq!(parse("cell a: 2 ** -b;"), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Pow(Atom(Number(2.0)), Neg(Atom(Ident(\"b\")))), output: false, doc: None, span: 0..16 })] }");
+    }
+
+    #[test]
+    fn test_elif() {
+        assert_eq!(parse(r#"cell a: if x ? 1 elif y ? 2 : 3;"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Condition { cond: Atom(Ident(\"x\")), true_branch: Atom(Number(1.0)), false_branch: Condition { cond: Atom(Ident(\"y\")), true_branch: Atom(Number(2.0)), false_branch: Atom(Number(3.0)) } }, output: false, doc: None, span: 0..32 })] }");
+        assert_eq!(
+            parse_error("cell a: if x ? 1 elif y ? 2;").message,
+            "expected `elif` or `:` before the false branch, found `;`"
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(parse(r#"assert a >= 0 : "negative"; assert assert;"#), "AST { nodes: [Assert(Assert { expr: Compare(GreaterEqual, Atom(Ident(\"a\")), Atom(Number(0.0))), message: Some(\"negative\"), span: 0..27 }), Assert(Assert { expr: Atom(Ident(\"assert\")), message: None, span: 28..42 })] }");
+        assert_eq!(
+            parse_error("assert a : 1;").message,
+            "expected the message of the assertion, found `1`"
+        );
+    }
+
+    #[test]
+    fn test_time_index() {
+        assert_eq!(parse("cell b[t]: b[t - 1];"), "AST { nodes: [Cell(Cell { name: \"b\", index: Some(\"t\"), expr: Index(Atom(Ident(\"b\")), Sub(Atom(Ident(\"t\")), Atom(Number(1.0)))), output: false, doc: None, span: 0..20 })] }");
+        assert_eq!(
+            parse_error("cell b[1]: 1;").message,
+            "expected the name of the period, e.g. `[t]`"
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        assert_eq!(parse("output cell a: output;"), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Atom(Ident(\"output\")), output: true, doc: None, span: 0..22 })] }");
+        assert_eq!(
+            parse_error("output param a;").message,
+            "expected `param`, `cell`, `import` or a directive, found `output`"
+        );
+    }
+
+    #[test]
+    fn test_doc() {
+        let code = r#"
+            ## hourly price in usd
+            ## (list price)
+            param price;
+            ## dropped, not in front of a param or cell
+            @eq-tolerance
+            ## total cost
+            # regular comments are skipped
+            cell total: price *
+              ## dropped as well
+              2;
+            cell next: 1;
+        "#;
+        let ast = super::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let docs: Vec<_> = ast
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                super::Node::Param(x) => Some(x.doc.as_deref()),
+                super::Node::Cell(x) => Some(x.doc.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            vec![
+                Some("hourly price in usd\n(list price)"),
+                Some("total cost"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        assert_eq!(parse(r#"import "lib/costs.cell"; param a;"#), "AST { nodes: [Import(Import { path: \"lib/costs.cell\", alias: None, span: 0..24 }), Param(Param { name: \"a\", ty: None, doc: None, span: 25..33 })] }");
+        assert_eq!(parse(r#"import "costs.cell" as costs;"#), "AST { nodes: [Import(Import { path: \"costs.cell\", alias: Some(\"costs\"), span: 0..29 })] }");
+        assert_eq!(
+            parse_error(r#"import "costs.cell" as 1;"#).message,
+            "expected the name of the namespace, found `1`"
+        );
+        assert_eq!(
+            parse_error("import costs;").message,
+            "expected the path of the imported file, found `costs`"
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(parse(r#"cell a: [1, b][0] * -[][c + 1][2];"#), "AST { nodes: [Cell(Cell { name: \"a\", index: None, expr: Mul(Index(List([Atom(Number(1.0)), Atom(Ident(\"b\"))]), Atom(Number(0.0))), Neg(Index(Index(List([]), Add(Atom(Ident(\"c\")), Atom(Number(1.0)))), Atom(Number(2.0))))), output: false, doc: None, span: 0..34 })] }");
+        assert_eq!(
+            parse_error("cell a: [1 2];").message,
+            "expected `,` or `]` in the list, found `2`"
+        );
+        assert_eq!(
+            parse_error("cell a: b[1;").message,
+            "expected `]`, found `;`"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = parse_error("cell a: 1 + 2 cell b: 2;");
+        assert_eq!(
+            error.message,
+            "expected `;` after the cell expression, found `cell`"
+        );
+        assert_eq!(error.span, Some(14..18));
+
+        let error = parse_error("cell a: 1 +");
+        assert_eq!(error.message, "expected an expression, found end of input");
+        assert_eq!(error.span, Some(11..11));
+
+        let error = parse_error("cell a: if x > 1 1 : 2;");
+        assert_eq!(error.message, "expected `?` after the condition, found `1`");
+        assert_eq!(error.span, Some(17..18));
+
+        let error = parse_error("cell a: max(1 2);");
+        assert_eq!(
+            error.message,
+            "expected `,` or `)` in the arguments of `max`, found `2`"
+        );
+
+        let error = parse_error("a;");
+        assert_eq!(
+            error.message,
+            "expected `param`, `cell`, `import` or a directive, found `a`"
         );
-        assert_eq!(parse(r#"cell test2: (1 + 2) + 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", expr: Add(Add(Atom(Number(1.0)), Atom(Number(2.0))), Atom(Number(3.0))) })] }");
-        assert_eq!(parse(r#"cell test2: (1 / abc) + 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", expr: Add(Div(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))) })] }");
-        assert_eq!(parse(r#"cell test2: (1 + abc) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", expr: Sub(Add(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))) })] }");
-        assert_eq!(parse(r#"cell test2: (1 * abc) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", expr: Sub(Mul(Atom(Number(1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))) })] }");
-        assert_eq!(parse(r#"cell test2: (-1 * (abc)) - 3;"#), "AST { nodes: [Cell(Cell { name: \"test2\", expr: Sub(Mul(Atom(Number(-1.0)), Atom(Ident(\"abc\"))), Atom(Number(3.0))) })] }");
+        assert_eq!(error.span, Some(0..1));
     }
 }
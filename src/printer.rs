@@ -0,0 +1,282 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::parser::{Atom, Cell, Directive, Expr, Node, Operator, Param, ParamType, AST};
+
+/*
+
+Prints the AST back as source code. Parentheses are only emitted where the
+precedence or associativity of the operators requires them, so printing a
+parsed model gives a canonical formatting of it:
+
+cell a: ((1 + 2)) * 3   ;   # comment
+
+prints as
+
+cell a: (1 + 2) * 3;
+
+*/
+
+impl Display for AST {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for node in &self.nodes {
+            writeln!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Node::Param(x) => write!(f, "{}", x),
+            Node::Cell(x) => write!(f, "{}", x),
+            Node::Directive(x) => write!(f, "{}", x),
+            Node::Assert(x) => match &x.message {
+                Some(message) => write!(f, "assert {} : \"{}\";", x.expr, message),
+                None => write!(f, "assert {};", x.expr),
+            },
+            Node::Import(x) => match &x.alias {
+                Some(alias) => write!(f, "import \"{}\" as {};", x.path, alias),
+                None => write!(f, "import \"{}\";", x.path),
+            },
+        }
+    }
+}
+
+impl Display for Param {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.ty {
+            Some(ty) => write!(f, "param {}: {};", self.name, ty),
+            None => write!(f, "param {};", self.name),
+        }
+    }
+}
+
+impl Display for ParamType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let ty = match self {
+            ParamType::Number => "number",
+            ParamType::String => "string",
+            ParamType::Bool => "bool",
+        };
+        write!(f, "{}", ty)
+    }
+}
+
+impl Display for Cell {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.output {
+            write!(f, "output ")?;
+        }
+        match &self.index {
+            Some(index) => write!(f, "cell {}[{}]: {};", self.name, index, self.expr),
+            None => write!(f, "cell {}: {};", self.name, self.expr),
+        }
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "@{}", self.name)?;
+        if !self.arguments.is_empty() {
+            write!(f, "(")?;
+            write_list(f, &self.arguments)?;
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let op = match self {
+            Operator::Equals => "==",
+            Operator::NotEquals => "!=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Atom::Number(x) => write!(f, "{}", x),
+            Atom::String(x) => write!(f, "\"{}\"", x),
+            Atom::Bool(x) => write!(f, "{}", x),
+            Atom::Ident(x) => write!(f, "{}", x),
+            Atom::Call { name, arguments } => {
+                write!(f, "{}(", name)?;
+                write_list(f, arguments)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl Expr {
+    /// Same binding powers as `Token::precedence`. atoms and other
+    /// self-delimiting expressions bind the tightest. `-` takes a `**`
+    /// operand, `-b ** 2` is `-(b ** 2)`, so it binds like `**`.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Condition { .. } => 0,
+            Expr::Or(..) => 1,
+            Expr::And(..) => 2,
+            Expr::Compare(..) => 3,
+            Expr::Add(..) | Expr::Sub(..) => 4,
+            Expr::Mul(..) | Expr::Div(..) | Expr::Mod(..) => 5,
+            Expr::Pow(..) | Expr::Neg(..) => 6,
+            Expr::Not(..) => 7,
+            Expr::Index(..) | Expr::List(..) | Expr::Atom(..) => 8,
+        }
+    }
+
+    fn binary(&self) -> Option<(&Expr, String, &Expr)> {
+        let (l, op, r) = match self {
+            Expr::Add(l, r) => (l, "+".to_string(), r),
+            Expr::Sub(l, r) => (l, "-".to_string(), r),
+            Expr::Mul(l, r) => (l, "*".to_string(), r),
+            Expr::Div(l, r) => (l, "/".to_string(), r),
+            Expr::Mod(l, r) => (l, "%".to_string(), r),
+            Expr::Pow(l, r) => (l, "**".to_string(), r),
+            Expr::And(l, r) => (l, "&&".to_string(), r),
+            Expr::Or(l, r) => (l, "||".to_string(), r),
+            Expr::Compare(op, l, r) => (l, op.to_string(), r),
+            _ => return None,
+        };
+        Some((l, op, r))
+    }
+}
+
+/// Writes `expr`, parenthesized when it binds looser than `min_precedence`
+fn write_operand(f: &mut Formatter<'_>, expr: &Expr, min_precedence: u8) -> Result {
+    if expr.precedence() < min_precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if let Some((l, op, r)) = self.binary() {
+            let precedence = self.precedence();
+            // operators are left-associative except `**`, so the operand on
+            // the other side needs parentheses at the same precedence
+            let (l_min, r_min) = match self {
+                Expr::Pow(..) => (precedence + 1, precedence),
+                _ => (precedence, precedence + 1),
+            };
+            write_operand(f, l, l_min)?;
+            write!(f, " {} ", op)?;
+            return write_operand(f, r, r_min);
+        }
+        match self {
+            Expr::Atom(x) => write!(f, "{}", x),
+            Expr::Not(x) => {
+                write!(f, "!")?;
+                write_operand(f, x, self.precedence())
+            }
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                write_operand(f, x, self.precedence())
+            }
+            Expr::List(items) => {
+                write!(f, "[")?;
+                write_list(f, items)?;
+                write!(f, "]")
+            }
+            Expr::Index(list, index) => {
+                write_operand(f, list, self.precedence())?;
+                write!(f, "[{}]", index)
+            }
+            Expr::Condition {
+                cond,
+                true_branch,
+                false_branch,
+            } => {
+                write!(f, "if {} ? {}", cond, true_branch)?;
+                // print else-if chains flat, `if a ? 1 elif b ? 2 : 3`
+                let mut false_branch = false_branch;
+                while let Expr::Condition {
+                    cond,
+                    true_branch,
+                    false_branch: next,
+                } = false_branch.as_ref()
+                {
+                    write!(f, " elif {} ? {}", cond, true_branch)?;
+                    false_branch = next;
+                }
+                write!(f, " : {}", false_branch)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser, scanner};
+
+    fn print(input: &str) -> String {
+        let tokens = scanner::scan_spanned(input).unwrap();
+        parser::parse(tokens).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(
+            print("param a;   # comment\n@eq-tolerance(0.5) cell b  :a*2;"),
+            "param a;\n@eq-tolerance(0.5)\ncell b: a * 2;\n"
+        );
+        assert_eq!(
+            print("cell a: ((1 + 2)) * 3 - (4 - 5) - 6;"),
+            "cell a: (1 + 2) * 3 - (4 - 5) - 6;\n"
+        );
+        assert_eq!(
+            print(r#"cell a: if !(x > 1) && (y || z) ? max(1, "s") : -2.5;"#),
+            "cell a: if !(x > 1) && (y || z) ? max(1, \"s\") : -2.5;\n"
+        );
+        assert_eq!(
+            print("cell a: (-b) ** 2 + -(b ** 2) + 2 ** -b;"),
+            "cell a: (-b) ** 2 + -b ** 2 + 2 ** -b;\n"
+        );
+        assert_eq!(
+            print("cell a: if x ? 1 : (if y ? 2 : 3);"),
+            "cell a: if x ? 1 elif y ? 2 : 3;\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let code = r#"
+        param p: string;
+        cell a: 1 - (2 - 3) * (4 % 5) / 6;
+        cell b: if a > 1 || (a < 0 && !true) ? 1 + if a == 2 ? 1 : 2 : 3;
+        cell c: a != 1;
+        cell d: -a * -(b - 1) - -2;
+        cell e: (a ** b) ** 2 ** -c * -(a ** 2);
+        cell f: [1, [a, b][0], (-a)[1]][-a[0] + 1];
+        cell g: if a ? 1 : if b ? if c ? 2 : 3 : 4;
+        output cell h: g;
+        cell i[t]: if t == 0 ? 1 : i[t - 1] * 2;
+        assert h > 0 && if a ? b : c : "h";
+        "#;
+        let printed = print(code);
+        assert_eq!(print(&printed), printed);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::bail;
+use regex::Regex;
+
+use crate::sema::is_private;
+
+/*
+
+--query "total,cost_*,/^margin_(low|high)$/"
+
+Plain names are kept as they are, `*` matches any run of characters and
+`/.../` is a regular expression. Patterns expand to the matching cells in
+declaration order, skipping private cells.
+
+*/
+
+/// Expands the patterns of a `--query` against the names of the declared
+/// `cells`. A name matched by several patterns is only kept once.
+pub fn expand(query: &str, cells: &[&str]) -> Result<Vec<String>, anyhow::Error> {
+    let mut names: Vec<String> = vec![];
+    for item in split(query).into_iter().filter(|item| !item.is_empty()) {
+        let pattern = if item.len() >= 2 && item.starts_with('/') && item.ends_with('/') {
+            Regex::new(&item[1..item.len() - 1])
+                .map_err(|e| anyhow::anyhow!("invalid query pattern `{}`: {}", item, e))?
+        } else if item.contains('*') {
+            let glob: Vec<_> = item.split('*').map(regex::escape).collect();
+            Regex::new(&format!("^{}$", glob.join(".*")))?
+        } else {
+            if !names.iter().any(|x| x == item) {
+                names.push(item.to_string());
+            }
+            continue;
+        };
+
+        let matched: Vec<_> = cells
+            .iter()
+            .filter(|cell| !is_private(cell) && pattern.is_match(cell))
+            .collect();
+        if matched.is_empty() {
+            bail!("query pattern `{}` matches no cells", item);
+        }
+        for cell in matched {
+            if !names.iter().any(|x| x == cell) {
+                names.push(cell.to_string());
+            }
+        }
+    }
+    if names.is_empty() {
+        bail!("query matched no cells");
+    }
+    Ok(names)
+}
+
+/// Splits on the commas outside of `/.../`
+fn split(query: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut start = 0;
+    let mut in_regex = false;
+    for (i, c) in query.char_indices() {
+        match c {
+            '/' if i == start => in_regex = true,
+            '/' if in_regex => in_regex = false,
+            ',' if !in_regex => {
+                items.push(query[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(query[start..].trim());
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let cells = [
+            "cost_cpu",
+            "total",
+            "cost_ram",
+            "_cost_scratch",
+            "margin_low",
+            "margin_high",
+        ];
+        assert_eq!(
+            expand("total,cost_*", &cells).unwrap(),
+            vec!["total", "cost_cpu", "cost_ram"]
+        );
+        assert_eq!(
+            expand("/^margin_(low|high)$/,margin_low", &cells).unwrap(),
+            vec!["margin_low", "margin_high"]
+        );
+        assert_eq!(
+            expand("/^cost_.{2,3}$/", &cells).unwrap(),
+            vec!["cost_cpu", "cost_ram"]
+        );
+        // plain names aren't checked here, the interpreter reports them
+        assert_eq!(expand("missing", &cells).unwrap(), vec!["missing"]);
+        assert!(expand("x_*", &cells).is_err());
+        assert!(expand("/(/", &cells).is_err());
+        assert_eq!(
+            expand(" , ", &cells).unwrap_err().to_string(),
+            "query matched no cells"
+        );
+    }
+}
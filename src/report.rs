@@ -0,0 +1,233 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::bail;
+use serde_json::json;
+
+use crate::{
+    diagnostic::{locate, Diagnostic, Severity},
+    import::SourceMap,
+};
+
+/*
+
+$ cell-script check --format json app.cell
+[
+  {
+    "severity": "warning",
+    "message": "cell `a` is nested 40 levels deep (limit 32)",
+    "hint": "move sub-expressions into their own cells",
+    "file": "app.cell",
+    "start": { "line": 3, "column": 1 },
+    "end": { "line": 3, "column": 96 }
+  }
+]
+
+*/
+
+/// How `check` prints diagnostics
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    /// SARIF 2.1.0, for code review bots and GitHub annotations
+    Sarif,
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            _ => bail!("unrecognized report format `{}`", s),
+        }
+    }
+}
+
+pub fn render(
+    diagnostics: &[Diagnostic],
+    source_map: &SourceMap,
+    format: ReportFormat,
+    color: bool,
+) -> String {
+    match format {
+        ReportFormat::Text => diagnostics
+            .iter()
+            .map(|diagnostic| source_map.render(diagnostic, color))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ReportFormat::Json => {
+            let items: Vec<_> = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let mut item = json!({
+                        "severity": level(diagnostic.severity),
+                        "message": diagnostic.message,
+                        "hint": diagnostic.hint,
+                    });
+                    if let Some(location) = Location::of(diagnostic, source_map) {
+                        item["file"] = json!(location.file);
+                        item["start"] =
+                            json!({ "line": location.start.0, "column": location.start.1 });
+                        item["end"] = json!({ "line": location.end.0, "column": location.end.1 });
+                    }
+                    item
+                })
+                .collect();
+            serde_json::to_string_pretty(&items).unwrap()
+        }
+        ReportFormat::Sarif => {
+            let results: Vec<_> = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    let text = match &diagnostic.hint {
+                        Some(hint) => format!("{}\nhint: {}", diagnostic.message, hint),
+                        None => diagnostic.message.clone(),
+                    };
+                    let mut result = json!({
+                        "level": level(diagnostic.severity),
+                        "message": { "text": text },
+                    });
+                    if let Some(location) = Location::of(diagnostic, source_map) {
+                        result["locations"] = json!([{
+                            "physicalLocation": {
+                                "artifactLocation": { "uri": location.file },
+                                "region": {
+                                    "startLine": location.start.0,
+                                    "startColumn": location.start.1,
+                                    "endLine": location.end.0,
+                                    "endColumn": location.end.1,
+                                },
+                            },
+                        }]);
+                    }
+                    result
+                })
+                .collect();
+            let sarif = json!({
+                "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                "version": "2.1.0",
+                "runs": [{
+                    "tool": {
+                        "driver": {
+                            "name": env!("CARGO_PKG_NAME"),
+                            "version": env!("CARGO_PKG_VERSION"),
+                        },
+                    },
+                    "results": results,
+                }],
+            });
+            serde_json::to_string_pretty(&sarif).unwrap()
+        }
+    }
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    }
+}
+
+/// 1-based `(line, column)` of both ends of a diagnostic's span, in the
+/// file it points to. `end` is the position right after the span.
+struct Location {
+    file: String,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+impl Location {
+    fn of(diagnostic: &Diagnostic, source_map: &SourceMap) -> Option<Self> {
+        let span = diagnostic.span.as_ref()?;
+        if source_map.files.is_empty() {
+            return None;
+        }
+        let (file, start) = source_map.locate(span.start);
+        let end = start + (span.end - span.start);
+        let (start_line, start_column, _) = locate(&file.content, start);
+        let (end_line, end_column, _) = locate(&file.content, end);
+        Some(Self {
+            file: file.path.display().to_string(),
+            start: (start_line, start_column),
+            end: (end_line, end_column),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn source_map() -> SourceMap {
+        let mut map = SourceMap::default();
+        map.add(
+            PathBuf::from("app.cell"),
+            "param a;\ncell b: a + 1;".to_string(),
+        );
+        map
+    }
+
+    #[test]
+    fn test_json() {
+        let diagnostics = [
+            Diagnostic::warning("unused")
+                .with_span(9..14)
+                .with_hint("remove it"),
+            Diagnostic::new("no span"),
+        ];
+        let report: Value = serde_json::from_str(&render(
+            &diagnostics,
+            &source_map(),
+            ReportFormat::Json,
+            false,
+        ))
+        .unwrap();
+        assert_eq!(
+            report,
+            json!([
+                {
+                    "severity": "warning",
+                    "message": "unused",
+                    "hint": "remove it",
+                    "file": "app.cell",
+                    "start": { "line": 2, "column": 1 },
+                    "end": { "line": 2, "column": 6 },
+                },
+                { "severity": "error", "message": "no span", "hint": null },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_sarif() {
+        let diagnostics = [Diagnostic::new("bad").with_span(0..5)];
+        let report: Value = serde_json::from_str(&render(
+            &diagnostics,
+            &source_map(),
+            ReportFormat::Sarif,
+            false,
+        ))
+        .unwrap();
+        assert_eq!(report["version"], "2.1.0");
+        let result = &report["runs"][0]["results"][0];
+        assert_eq!(result["level"], "error");
+        assert_eq!(result["message"]["text"], "bad");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"],
+            json!({ "startLine": 1, "startColumn": 1, "endLine": 1, "endColumn": 6 })
+        );
+    }
+}
@@ -1,40 +1,122 @@
-use std::iter::Peekable;
+use std::{fmt::Display, iter::Peekable};
 
-use anyhow::bail;
+use crate::diagnostic::{Diagnostic, Span};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Token<'a> {
-    Param,           // param
-    Cell,            // cell
-    Ident(&'a str),  //
-    If,              // if
-    QMark,           // ?
-    SemiColon,       // ;
-    Colon,           // :
-    Mul,             // *
-    Mod,             // %
-    Add,             // +
-    Sub,             // -
-    Div,             // /
-    Number(&'a str), // 1, 1.0, -1
-    ParOpen,         // (
-    ParClose,        // )
-    Comma,           // ,
-    Greater,         // >
-    GreaterEqual,    // >=
-    Less,            // <
-    LessEqual,       // <=
-    Equal,           // ==
+    Param,              // param
+    Cell,               // cell
+    Import,             // import
+    Ident(&'a str),     //
+    If,                 // if
+    Elif,               // elif
+    True,               // true
+    False,              // false
+    QMark,              // ?
+    SemiColon,          // ;
+    Colon,              // :
+    Mul,                // *
+    Pow,                // **
+    Mod,                // %
+    Add,                // +
+    Sub,                // -
+    Div,                // /
+    Number(&'a str),    // 1, 1.0, 1e-9
+    String(&'a str),    // "gcp"
+    ParOpen,            // (
+    ParClose,           // )
+    BracketOpen,        // [
+    BracketClose,       // ]
+    Comma,              // ,
+    Greater,            // >
+    GreaterEqual,       // >=
+    Less,               // <
+    LessEqual,          // <=
+    Equal,              // ==
+    NotEqual,           // !=
+    And,                // &&
+    Or,                 // ||
+    Not,                // !
+    Directive(&'a str), // @eq-tolerance
+    Doc(&'a str),       // ## text
+}
+
+impl<'a> Display for Token<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Param => "param",
+            Token::Cell => "cell",
+            Token::Import => "import",
+            Token::Ident(x) | Token::Number(x) => x,
+            Token::If => "if",
+            Token::Elif => "elif",
+            Token::True => "true",
+            Token::False => "false",
+            Token::QMark => "?",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Mul => "*",
+            Token::Pow => "**",
+            Token::Mod => "%",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Div => "/",
+            Token::ParOpen => "(",
+            Token::ParClose => ")",
+            Token::BracketOpen => "[",
+            Token::BracketClose => "]",
+            Token::Comma => ",",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Not => "!",
+            Token::Directive(x) => return write!(f, "`@{}`", x),
+            Token::Doc(_) => "## ...",
+            Token::String(x) => return write!(f, "`\"{}\"`", x),
+        };
+        write!(f, "`{}`", text)
+    }
 }
 
 pub fn scan<'a>(input: &'a str) -> Result<Vec<Token<'a>>, anyhow::Error> {
+    let tokens = scan_spanned(input)?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Same as [`scan`] but every token is paired with its location in `input`.
+/// Spans count chars, the slices of `input` in the tokens are taken at the
+/// byte offsets of those chars.
+pub fn scan_spanned<'a>(input: &'a str) -> Result<Vec<(Token<'a>, Span)>, anyhow::Error> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().enumerate().peekable();
+    let mut spans = Vec::new();
+    // (char index, byte offset, char)
+    let mut chars = input
+        .char_indices()
+        .enumerate()
+        .map(|(i, (at, c))| (i, at, c))
+        .peekable();
+    let len = input.chars().count();
 
-    while let Some((i, c)) = chars.next() {
+    while let Some((i, at, c)) = chars.next() {
+        let scanned = tokens.len();
         match c {
+            '#' if matches!(chars.peek(), Some((_, _, '#'))) => {
+                let mut end = input.len();
+                for (_, at, c) in chars.by_ref() {
+                    if c == '\n' {
+                        end = at;
+                        break;
+                    }
+                }
+                tokens.push(Token::Doc(input[at + 2..end].trim()));
+            }
             '#' => {
-                'inner: while let Some((_, c)) = chars.next() {
+                'inner: for (_, _, c) in chars.by_ref() {
                     if c == '\n' {
                         break 'inner;
                     }
@@ -46,70 +128,100 @@ pub fn scan<'a>(input: &'a str) -> Result<Vec<Token<'a>>, anyhow::Error> {
             '%' => tokens.push(Token::Mod),
             ':' => tokens.push(Token::Colon),
             '+' => tokens.push(Token::Add),
+            '*' if matches!(chars.peek(), Some((_, _, '*'))) => {
+                chars.next();
+                tokens.push(Token::Pow);
+            }
             '*' => tokens.push(Token::Mul),
-            '>' if matches!(chars.peek(), Some((_, '='))) => {
+            '>' if matches!(chars.peek(), Some((_, _, '='))) => {
                 chars.next();
                 tokens.push(Token::GreaterEqual);
             }
             '>' => tokens.push(Token::Greater),
-            '<' if matches!(chars.peek(), Some((_, '='))) => {
+            '<' if matches!(chars.peek(), Some((_, _, '='))) => {
                 chars.next();
                 tokens.push(Token::LessEqual)
             }
             '<' => tokens.push(Token::Less),
-            '=' if matches!(chars.peek(), Some((_, '='))) => {
+            '=' if matches!(chars.peek(), Some((_, _, '='))) => {
                 chars.next();
                 tokens.push(Token::Equal);
             }
-            '-' => {
-                if let Some((_, next_c)) = chars.peek() {
-                    if next_c.is_numeric() {
-                        tokens.push(scan_number(input, i, &mut chars)?);
-                    } else {
-                        tokens.push(Token::Sub);
-                    }
-                } else {
-                    tokens.push(Token::Sub);
-                }
+            '&' if matches!(chars.peek(), Some((_, _, '&'))) => {
+                chars.next();
+                tokens.push(Token::And);
             }
+            '|' if matches!(chars.peek(), Some((_, _, '|'))) => {
+                chars.next();
+                tokens.push(Token::Or);
+            }
+            '!' if matches!(chars.peek(), Some((_, _, '='))) => {
+                chars.next();
+                tokens.push(Token::NotEqual);
+            }
+            '!' => tokens.push(Token::Not),
+            // always an operator, `-1` is parsed as the negation of `1` so
+            // `t-1` is a subtraction
+            '-' => tokens.push(Token::Sub),
             '/' => tokens.push(Token::Div),
+            '@' => tokens.push(scan_directive(input, (i, at), &mut chars)?),
+            '"' => tokens.push(scan_string(input, (i, at), &mut chars)?),
             '(' => tokens.push(Token::ParOpen),
             ')' => tokens.push(Token::ParClose),
+            '[' => tokens.push(Token::BracketOpen),
+            ']' => tokens.push(Token::BracketClose),
             x if x.is_whitespace() => { /* skip */ }
-            x if x.is_numeric() => {
-                tokens.push(scan_number(input, i, &mut chars)?);
+            x if x.is_ascii_digit() => {
+                tokens.push(scan_number(input, at, &mut chars)?);
             }
-            x if x.is_ascii_alphabetic() => {
-                tokens.push(scan_ident(input, i, &mut chars)?);
+            x if x.is_ascii_alphabetic() || x == '_' => {
+                tokens.push(scan_ident(input, at, &mut chars)?);
             }
             x => {
-                bail!("unexpected character `{}`", x)
+                return Err(Diagnostic::new(format!("unexpected character `{}`", x))
+                    .with_span(i..i + 1)
+                    .with_hint("names may only contain letters, digits, `_` and `.`")
+                    .into());
             }
         }
+        if tokens.len() > scanned {
+            let end = chars.peek().map(|(j, _, _)| *j).unwrap_or(len);
+            spans.push(i..end);
+        }
     }
 
-    Ok(tokens)
+    Ok(tokens.into_iter().zip(spans).collect())
 }
 
-fn scan_number<'a, T: Iterator<Item = (usize, char)>>(
+/// The byte offset of the next char, the end of the token being scanned
+fn end_of<T: Iterator<Item = (usize, usize, char)>>(input: &str, chars: &mut Peekable<T>) -> usize {
+    chars.peek().map(|(_, at, _)| *at).unwrap_or(input.len())
+}
+
+fn scan_number<'a, T: Iterator<Item = (usize, usize, char)>>(
     input: &'a str,
-    start_char_idx: usize,
+    start: usize,
     chars: &mut Peekable<T>,
 ) -> Result<Token<'a>, anyhow::Error> {
-    let mut offset = 0;
     let mut number = String::new();
-    number.push_str(&input[start_char_idx..start_char_idx]);
+    number.push_str(&input[start..start + 1]);
 
-    while let Some((_, c)) = chars.peek() {
-        let c = *c;
+    while let Some((_, at, c)) = chars.peek() {
+        let (at, c) = (*at, *c);
         number.push(c);
         if c == '.' {
-            offset += 1;
             chars.next();
+        } else if matches!(c, 'e' | 'E') && is_exponent(&input[at + 1..]) {
+            // exponent, e.g. `1e-9`. consume the sign as well so the
+            // digits after it can be parsed
+            chars.next();
+            if let Some((_, _, sign @ ('-' | '+'))) = chars.peek() {
+                number.push(*sign);
+                chars.next();
+            }
         } else {
             match number.parse::<f64>() {
                 Ok(_) => {
-                    offset += 1;
                     chars.next();
                 }
                 Err(_) => {
@@ -119,30 +231,82 @@ fn scan_number<'a, T: Iterator<Item = (usize, char)>>(
         }
     }
 
-    let number = &input[start_char_idx..=start_char_idx + offset];
-    Ok(Token::Number(number))
+    Ok(Token::Number(&input[start..end_of(input, chars)]))
+}
+
+fn is_exponent(rest: &str) -> bool {
+    let rest = rest.strip_prefix(['-', '+']).unwrap_or(rest);
+    matches!(rest.chars().next(), Some(x) if x.is_ascii_digit())
+}
+
+/// `start` is the char index and the byte offset of the opening `"`
+fn scan_string<'a, T: Iterator<Item = (usize, usize, char)>>(
+    input: &'a str,
+    (start_char_idx, start): (usize, usize),
+    chars: &mut Peekable<T>,
+) -> Result<Token<'a>, anyhow::Error> {
+    for (_, at, c) in chars.by_ref() {
+        match c {
+            '"' => return Ok(Token::String(&input[start + 1..at])),
+            '\n' => break,
+            _ => {}
+        }
+    }
+    Err(Diagnostic::new("unterminated string")
+        .with_span(start_char_idx..start_char_idx + 1)
+        .with_hint("strings start and end with `\"` on the same line")
+        .into())
+}
+
+/// `start` is the char index and the byte offset of the `@`
+fn scan_directive<'a, T: Iterator<Item = (usize, usize, char)>>(
+    input: &'a str,
+    (start_char_idx, start): (usize, usize),
+    chars: &mut Peekable<T>,
+) -> Result<Token<'a>, anyhow::Error> {
+    while let Some((_, _, c)) = chars.peek() {
+        if c.is_alphanumeric() || *c == '-' {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    let end = end_of(input, chars);
+    if end == start + 1 {
+        return Err(Diagnostic::new("expected a directive name after `@`")
+            .with_span(start_char_idx..start_char_idx + 1)
+            .with_hint("directives look like `@eq-tolerance(1e-9)`")
+            .into());
+    }
+
+    Ok(Token::Directive(&input[start + 1..end]))
 }
 
-fn scan_ident<'a, T: Iterator<Item = (usize, char)>>(
+fn scan_ident<'a, T: Iterator<Item = (usize, usize, char)>>(
     input: &'a str,
-    start_char_idx: usize,
+    start: usize,
     chars: &mut Peekable<T>,
 ) -> Result<Token<'a>, anyhow::Error> {
-    let mut offset = 0;
-    while let Some((_, c)) = chars.peek() {
-        if c.is_alphanumeric() {
-            offset += 1;
+    while let Some((_, at, c)) = chars.peek() {
+        // `.` is allowed between two parts of a qualified name (e.g. `infra.total`)
+        let is_qualifier = *c == '.'
+            && matches!(input[at + 1..].chars().next(), Some(x) if x.is_ascii_alphabetic() || x == '_');
+        if c.is_alphanumeric() || *c == '_' || is_qualifier {
             chars.next();
         } else {
             break;
         }
     }
 
-    let ident = &input[start_char_idx..=start_char_idx + offset];
+    let ident = &input[start..end_of(input, chars)];
     let token = match ident {
         "param" => Token::Param,
         "cell" => Token::Cell,
+        "import" => Token::Import,
         "if" => Token::If,
+        "elif" => Token::Elif,
+        "true" => Token::True,
+        "false" => Token::False,
         x => Token::Ident(x),
     };
     Ok(token)
@@ -164,6 +328,16 @@ mod tests {
             scan("param   a; # test\n\nparam abc   ;").unwrap(),
             vec![Param, Ident("a"), SemiColon, Param, Ident("abc"), SemiColon,]
         );
+
+        assert_eq!(
+            scan("param math_score;").unwrap(),
+            vec![Param, Ident("math_score"), SemiColon,]
+        );
+
+        assert_eq!(
+            scan("param infra.total;").unwrap(),
+            vec![Param, Ident("infra.total"), SemiColon,]
+        );
     }
 
     #[test]
@@ -268,14 +442,145 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_doc() {
+        assert_eq!(
+            scan("## monthly cost\n#comment\ncell a: 1; ##").unwrap(),
+            vec![
+                Doc("monthly cost"),
+                Cell,
+                Ident("a"),
+                Colon,
+                Number("1"),
+                SemiColon,
+                Doc("")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_unicode() {
+        assert_eq!(
+            scan_spanned("## café ☕\ncell a: 1;").unwrap()[..3],
+            [
+                (Doc("café ☕"), 0..10),
+                (Cell, 10..14),
+                (Ident("a"), 15..16)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(
+            scan("@eq-tolerance(1e-9)").unwrap(),
+            vec![Directive("eq-tolerance"), ParOpen, Number("1e-9"), ParClose,]
+        );
+        assert!(scan("@ x").is_err());
+        assert_eq!(scan("\"é\" @periods(3)").unwrap()[1], Directive("periods"));
+        assert_eq!(
+            scan("\"é\" infra.total.x").unwrap()[1],
+            Ident("infra.total.x")
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(
+            scan_spanned("cell ab: 1.5;").unwrap(),
+            vec![
+                (Cell, 0..4),
+                (Ident("ab"), 5..7),
+                (Colon, 7..8),
+                (Number("1.5"), 9..12),
+                (SemiColon, 12..13),
+            ]
+        );
+        assert_eq!(scan_spanned("a >= b").unwrap()[1], (GreaterEqual, 2..4));
+
+        let error = scan_spanned("cell a: 1 $ 2;").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.span, Some(10..11));
+    }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(
+            scan("if a > 1 && !b || true && !false").unwrap(),
+            vec![
+                If,
+                Ident("a"),
+                Greater,
+                Number("1"),
+                And,
+                Not,
+                Ident("b"),
+                Or,
+                True,
+                And,
+                Not,
+                False,
+            ]
+        );
+        assert!(scan("a & b").is_err());
+        assert_eq!(
+            scan("a != !b").unwrap(),
+            vec![Ident("a"), NotEqual, Not, Ident("b")]
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(
+            scan(r#"if provider == "gcp" ? 1 : "" "#).unwrap(),
+            vec![
+                If,
+                Ident("provider"),
+                Equal,
+                String("gcp"),
+                QMark,
+                Number("1"),
+                Colon,
+                String(""),
+            ]
+        );
+        assert!(scan("cell a: \"gcp;\n").is_err());
+        assert_eq!(
+            scan_spanned(r#"a == "é€" ? b"#).unwrap()[2..],
+            [(String("é€"), 5..9), (QMark, 10..11), (Ident("b"), 12..13)]
+        );
+        let error = scan_spanned("\"é\" \"x\n").unwrap_err();
+        assert_eq!(error.downcast_ref::<Diagnostic>().unwrap().span, Some(4..5));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert_eq!(
+            scan("a[0]").unwrap(),
+            vec![Ident("a"), BracketOpen, Number("0"), BracketClose]
+        );
+    }
+
     #[test]
     fn test_number() {
+        assert_eq!(
+            scan("2**3*4").unwrap(),
+            vec![Number("2"), Pow, Number("3"), Mul, Number("4")]
+        );
         assert_eq!(scan("123456").unwrap(), vec![Number("123456"),]);
         assert_eq!(scan("12.156").unwrap(), vec![Number("12.156"),]);
-        assert_eq!(scan("-123").unwrap(), vec![Number("-123"),]);
-        assert_eq!(scan("-123.123").unwrap(), vec![Number("-123.123"),]);
+        assert_eq!(scan("-123").unwrap(), vec![Sub, Number("123"),]);
+        assert_eq!(scan("-123.123").unwrap(), vec![Sub, Number("123.123"),]);
+        assert_eq!(scan("5-1").unwrap(), vec![Number("5"), Sub, Number("1"),]);
+        assert_eq!(scan("a-1").unwrap(), vec![Ident("a"), Sub, Number("1"),]);
         assert_eq!(scan("- 123").unwrap(), vec![Sub, Number("123"),]);
         assert_eq!(scan("- abc").unwrap(), vec![Sub, Ident("abc"),]);
-        // FIXME: assert_eq!(scan("-abc").unwrap(), vec![Sub, Ident("abc"),]);
+        assert_eq!(scan("1e-9").unwrap(), vec![Number("1e-9"),]);
+        assert_eq!(scan("2.5E3").unwrap(), vec![Number("2.5E3"),]);
+        assert_eq!(scan("2e+3").unwrap(), vec![Number("2e+3"),]);
+        assert_eq!(scan("-abc").unwrap(), vec![Sub, Ident("abc"),]);
+        let error = scan_spanned("cell a: ½;").unwrap_err();
+        assert_eq!(error.downcast_ref::<Diagnostic>().unwrap().span, Some(8..9));
+        assert!(scan("1½").is_err());
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::Display;
+
+use indexmap::IndexMap;
+
+use crate::parser::{Node, AST};
+
+/// A semantic difference between two versions of a model. Formulas are
+/// rendered with the pretty-printer, so formatting-only edits never show up.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed {
+        name: String,
+        before: String,
+        after: String,
+    },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(node) => write!(f, "+ {}", node),
+            Change::Removed(node) => write!(f, "- {}", node),
+            Change::Changed {
+                name,
+                before,
+                after,
+            } => write!(f, "~ {}\n    - {}\n    + {}", name, before, after),
+        }
+    }
+}
+
+/// `cell total`, `param price` or `@eq-tolerance` and the printed node.
+/// Printing the whole node compares `output`, the period of a cell and the
+/// type of a param too, and never leaves a side of a change empty.
+fn entries(ast: &AST) -> IndexMap<String, (String, &Node)> {
+    let mut entries = IndexMap::new();
+    for node in &ast.nodes {
+        let key = match node {
+            Node::Param(param) => format!("param {}", param.name),
+            Node::Import(import) => format!("import {:?}", import.path),
+            Node::Cell(cell) => format!("cell {}", cell.name),
+            Node::Assert(assert) => format!("assert {}", assert.expr),
+            Node::Directive(directive) => format!("@{}", directive.name),
+        };
+        entries.insert(key, (node.to_string(), node));
+    }
+    entries
+}
+
+pub fn sdiff(before: &AST, after: &AST) -> Vec<Change> {
+    let before = entries(before);
+    let after = entries(after);
+    let mut changes = vec![];
+
+    for (key, (body, node)) in &before {
+        match after.get(key) {
+            None => changes.push(Change::Removed(node.to_string())),
+            Some((after_body, _)) if after_body != body => changes.push(Change::Changed {
+                name: key.clone(),
+                before: body.clone(),
+                after: after_body.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, (_, node)) in &after {
+        if !before.contains_key(key) {
+            changes.push(Change::Added(node.to_string()));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, scanner};
+
+    fn diff(before: &str, after: &str) -> Vec<String> {
+        let parse = |code| parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        sdiff(&parse(before), &parse(after))
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_formatting_only() {
+        assert!(diff(
+            "param a; cell b: a * 2 + 1;",
+            "# reformatted\nparam a;\ncell b:\n  (a * 2) + 1\n;"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        assert_eq!(
+            diff(
+                "param a; param old; cell b: a * 2; cell c: 1;",
+                "@eq-tolerance(0.1) param a; cell b: a * 3; cell c: 1; cell d: b + c;"
+            ),
+            vec![
+                "- param old;",
+                "~ cell b\n    - cell b: a * 2;\n    + cell b: a * 3;",
+                "+ @eq-tolerance(0.1)",
+                "+ cell d: b + c;",
+            ]
+        );
+
+        assert_eq!(
+            diff("cell a: 1; cell b: 2;", "output cell a: 1; cell b: 2;"),
+            vec!["~ cell a\n    - cell a: 1;\n    + output cell a: 1;"]
+        );
+        assert_eq!(
+            diff(
+                "@periods(2) param a; cell b[t]: 1; @iterate",
+                "@periods(2) param a: number; cell b: 1; @iterate(10, 0.1)"
+            ),
+            vec![
+                "~ param a\n    - param a;\n    + param a: number;",
+                "~ cell b\n    - cell b[t]: 1;\n    + cell b: 1;",
+                "~ @iterate\n    - @iterate\n    + @iterate(10, 0.1)",
+            ]
+        );
+    }
+}
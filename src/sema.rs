@@ -0,0 +1,660 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    diagnostic::{did_you_mean, Diagnostic, Span},
+    parser::{Atom, Cell, Expr, Node, AST},
+    types,
+};
+
+/*
+
+Static checks on a parsed model, run before it is evaluated. They report
+every problem at once instead of stopping at the first failing cell.
+
+*/
+
+/// Expressions nested deeper than this are rejected whatever the configured
+/// limits, the parser and the interpreter recurse over them.
+pub const HARD_MAX_DEPTH: usize = 128;
+
+/// Complexity limits, cells exceeding them get a warning.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// how deeply operators, conditions and calls may nest in one cell
+    pub max_depth: usize,
+    /// how many distinct cells and params one cell may reference
+    pub max_dependencies: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_dependencies: 16,
+        }
+    }
+}
+
+pub fn check(ast: &AST, limits: &Limits) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_duplicates(ast, &mut diagnostics);
+    check_undefined(ast, &mut diagnostics);
+    check_cycles(ast, &mut diagnostics);
+    check_unused(ast, &mut diagnostics);
+    diagnostics.extend(types::check(ast).1);
+    for node in &ast.nodes {
+        if let Node::Cell(cell) = node {
+            check_limits(cell, limits, &mut diagnostics);
+            if cell.output && is_private(&cell.name) {
+                diagnostics.push(
+                    Diagnostic::new(format!("private cell `{}` can't be an output", cell.name))
+                        .with_span(cell.span.clone())
+                        .with_hint("remove the leading `_` or the `output`"),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/// A name declared by more than one param or cell, the later one would
+/// silently replace the earlier
+fn check_duplicates(ast: &AST, diagnostics: &mut Vec<Diagnostic>) {
+    let mut declared: HashMap<&str, (&str, &Span)> = HashMap::new();
+    for node in &ast.nodes {
+        let (kind, name, span) = match node {
+            Node::Param(param) => ("param", &param.name, &param.span),
+            Node::Cell(cell) => ("cell", &cell.name, &cell.span),
+            _ => continue,
+        };
+        match declared.get(name.as_str()) {
+            Some((first_kind, first_span)) => diagnostics.push(
+                Diagnostic::new(format!(
+                    "{} `{}` is already defined as a {}",
+                    kind, name, first_kind
+                ))
+                .with_span(span.clone())
+                .with_note((*first_span).clone(), "first defined here")
+                .with_hint("rename one of them"),
+            ),
+            None => {
+                declared.insert(name, (kind, span));
+            }
+        }
+    }
+}
+
+/// Names that no param or cell declares, reported once per cell or assert
+fn check_undefined(ast: &AST, diagnostics: &mut Vec<Diagnostic>) {
+    let declared: BTreeSet<&str> = ast
+        .nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Param(param) => Some(param.name.as_str()),
+            Node::Cell(cell) => Some(cell.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    for node in &ast.nodes {
+        let (context, expr, span, index) = match node {
+            Node::Cell(cell) => (
+                format!("cell `{}`", cell.name),
+                &cell.expr,
+                &cell.span,
+                cell.index.as_deref(),
+            ),
+            Node::Assert(assert) => ("assertion".to_string(), &assert.expr, &assert.span, None),
+            _ => continue,
+        };
+        let mut names = BTreeSet::new();
+        references(expr, &mut names);
+        for name in names {
+            if declared.contains(name) || Some(name) == index {
+                continue;
+            }
+            let mut diagnostic = Diagnostic::new(format!(
+                "{} references `{}`, which is not defined",
+                context, name
+            ))
+            .with_span(span.clone());
+            if let Some(x) = did_you_mean(name, declared.iter().copied()) {
+                diagnostic = diagnostic.with_hint(format!("did you mean `{}`?", x));
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+}
+
+/// Warnings for params nothing references, cells no `output` cell or
+/// `assert` needs, and periods named like a param or cell
+fn check_unused(ast: &AST, diagnostics: &mut Vec<Diagnostic>) {
+    let mut referenced = BTreeSet::new();
+    let mut cells = HashMap::new();
+    let mut roots = BTreeSet::new();
+    for node in &ast.nodes {
+        match node {
+            Node::Cell(cell) => {
+                references(&cell.expr, &mut referenced);
+                cells.insert(cell.name.as_str(), cell);
+                if cell.output {
+                    roots.insert(cell.name.as_str());
+                }
+            }
+            Node::Assert(assert) => {
+                references(&assert.expr, &mut referenced);
+                references(&assert.expr, &mut roots);
+            }
+            _ => {}
+        }
+    }
+
+    for node in &ast.nodes {
+        if let Node::Param(param) = node {
+            if !referenced.contains(param.name.as_str()) {
+                diagnostics.push(
+                    Diagnostic::warning(format!("param `{}` is never used", param.name))
+                        .with_span(param.span.clone())
+                        .with_hint("remove it, or reference it from a cell"),
+                );
+            }
+        }
+    }
+
+    // without `output` cells every cell may be queried
+    if cells.values().any(|cell| cell.output) {
+        let reachable = reachable(&cells, roots);
+        for node in &ast.nodes {
+            if let Node::Cell(cell) = node {
+                if !reachable.contains(cell.name.as_str()) {
+                    diagnostics.push(
+                        Diagnostic::warning(format!(
+                            "cell `{}` isn't needed by any output cell or assert",
+                            cell.name
+                        ))
+                        .with_span(cell.span.clone())
+                        .with_hint("remove it, or mark it `output`"),
+                    );
+                }
+            }
+        }
+    }
+
+    for node in &ast.nodes {
+        if let Node::Cell(Cell {
+            name,
+            index: Some(index),
+            span,
+            ..
+        }) = node
+        {
+            let shadowed = ast.nodes.iter().find_map(|node| match node {
+                Node::Param(param) if param.name == *index => Some("param"),
+                Node::Cell(cell) if cell.name == *index => Some("cell"),
+                _ => None,
+            });
+            if let Some(kind) = shadowed {
+                diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "the period `{}` of cell `{}` shadows the {} `{}`",
+                        index, name, kind, index
+                    ))
+                    .with_span(span.clone())
+                    .with_hint("rename the period, e.g. `[t]`"),
+                );
+            }
+        }
+    }
+}
+
+/// The cells `roots` depend on, directly or through other cells, and the
+/// roots themselves
+fn reachable<'a>(
+    cells: &HashMap<&'a str, &'a Cell>,
+    roots: impl IntoIterator<Item = &'a str>,
+) -> BTreeSet<&'a str> {
+    let mut reachable = BTreeSet::new();
+    let mut stack: Vec<&str> = roots.into_iter().collect();
+    while let Some(name) = stack.pop() {
+        if let Some(cell) = cells.get(name) {
+            if reachable.insert(name) {
+                let mut dependencies = BTreeSet::new();
+                references(&cell.expr, &mut dependencies);
+                stack.extend(dependencies);
+            }
+        }
+    }
+    reachable
+}
+
+/// The model without the cells that neither the queried `cell_names` nor
+/// an assert depend on, so every permutation skips setting them up
+pub fn prune(ast: &AST, cell_names: &[&str]) -> AST {
+    let mut cells = HashMap::new();
+    let mut roots: BTreeSet<&str> = cell_names.iter().copied().collect();
+    for node in &ast.nodes {
+        match node {
+            Node::Cell(cell) => {
+                cells.insert(cell.name.as_str(), cell);
+            }
+            Node::Assert(assert) => references(&assert.expr, &mut roots),
+            _ => {}
+        }
+    }
+    let reachable = reachable(&cells, roots);
+    AST {
+        nodes: ast
+            .nodes
+            .iter()
+            .filter(|node| match node {
+                Node::Cell(cell) => reachable.contains(cell.name.as_str()),
+                _ => true,
+            })
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Cells that depend on themselves through other cells. Models with
+/// `@iterate` solve them, and time-indexed cells are checked period by
+/// period when they're evaluated.
+fn check_cycles(ast: &AST, diagnostics: &mut Vec<Diagnostic>) {
+    let iterates = ast
+        .nodes
+        .iter()
+        .any(|node| matches!(node, Node::Directive(x) if x.name == "iterate"));
+    if iterates {
+        return;
+    }
+    let cells: HashMap<&str, &Cell> = ast
+        .nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Cell(cell) if cell.index.is_none() => Some((cell.name.as_str(), cell)),
+            _ => None,
+        })
+        .collect();
+    let mut colors = HashMap::new();
+    for node in &ast.nodes {
+        if let Node::Cell(cell) = node {
+            if cells.contains_key(cell.name.as_str()) {
+                visit(&cell.name, &cells, &mut colors, &mut vec![], diagnostics);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+    /// on the path being explored
+    Gray,
+    /// every cell it depends on was explored
+    Black,
+}
+
+/// Depth-first search from `name`, reporting a cycle for every edge back
+/// to a cell on `path`
+fn visit<'a>(
+    name: &'a str,
+    cells: &HashMap<&'a str, &'a Cell>,
+    colors: &mut HashMap<&'a str, Color>,
+    path: &mut Vec<&'a str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    match colors.get(name) {
+        Some(Color::Black) => return,
+        Some(Color::Gray) => {
+            let start = path.iter().position(|x| *x == name).unwrap();
+            let cycle: Vec<_> = path[start..].iter().chain([&name]).copied().collect();
+            diagnostics.push(
+                Diagnostic::new(format!("cyclic dependency: {}", cycle.join(" -> ")))
+                    .with_span(cells[name].span.clone())
+                    .with_hint("break the cycle, or solve it by iteration with `@iterate`"),
+            );
+            return;
+        }
+        None => {}
+    }
+    colors.insert(name, Color::Gray);
+    path.push(name);
+    let mut dependencies = BTreeSet::new();
+    references(&cells[name].expr, &mut dependencies);
+    for dependency in dependencies {
+        if let Some((dependency, _)) = cells.get_key_value(dependency) {
+            visit(dependency, cells, colors, path, diagnostics);
+        }
+    }
+    path.pop();
+    colors.insert(name, Color::Black);
+}
+
+/// `identifiers`, without the table names passed to `tier_lookup` and
+/// `interp`
+fn references<'a>(expr: &'a Expr, out: &mut BTreeSet<&'a str>) {
+    match expr {
+        Expr::Atom(Atom::Ident(name)) => {
+            out.insert(name);
+        }
+        Expr::Atom(Atom::Call { name, arguments })
+            if (name == "tier_lookup" || name == "interp") && arguments.len() == 2 =>
+        {
+            references(&arguments[0], out);
+            if !matches!(arguments[1], Expr::Atom(Atom::Ident(_))) {
+                references(&arguments[1], out);
+            }
+        }
+        _ => {
+            for child in children(expr) {
+                references(child, out);
+            }
+        }
+    }
+}
+
+/// Cells whose name (or its last part, in `pricing._rate`) starts with `_`
+/// are scratch values, they can't be queried, linked or marked `output`.
+pub fn is_private(name: &str) -> bool {
+    name.rsplit('.').next().unwrap_or(name).starts_with('_')
+}
+
+fn check_limits(cell: &Cell, limits: &Limits, diagnostics: &mut Vec<Diagnostic>) {
+    let depth = depth(&cell.expr);
+    if depth > HARD_MAX_DEPTH {
+        diagnostics.push(
+            Diagnostic::new(format!(
+                "cell `{}` is nested {} levels deep, the maximum is {}",
+                cell.name, depth, HARD_MAX_DEPTH
+            ))
+            .with_span(cell.span.clone())
+            .with_hint("move sub-expressions into their own cells"),
+        );
+    } else if depth > limits.max_depth {
+        diagnostics.push(
+            Diagnostic::warning(format!(
+                "cell `{}` is nested {} levels deep (limit {})",
+                cell.name, depth, limits.max_depth
+            ))
+            .with_span(cell.span.clone())
+            .with_hint("move sub-expressions into their own cells"),
+        );
+    }
+
+    let mut dependencies = BTreeSet::new();
+    identifiers(&cell.expr, &mut dependencies);
+    if dependencies.len() > limits.max_dependencies {
+        diagnostics.push(
+            Diagnostic::warning(format!(
+                "cell `{}` depends on {} cells and params (limit {})",
+                cell.name,
+                dependencies.len(),
+                limits.max_dependencies
+            ))
+            .with_span(cell.span.clone())
+            .with_hint("split it into intermediate cells that each combine a few of them"),
+        );
+    }
+}
+
+/// The direct sub-expressions of `expr`
+pub fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Atom(Atom::Call { arguments, .. }) => arguments.iter().collect(),
+        Expr::Atom(_) => vec![],
+        Expr::Add(l, r)
+        | Expr::Sub(l, r)
+        | Expr::Mul(l, r)
+        | Expr::Div(l, r)
+        | Expr::Mod(l, r)
+        | Expr::Pow(l, r)
+        | Expr::Compare(_, l, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r)
+        | Expr::Index(l, r) => vec![l, r],
+        Expr::Not(x) | Expr::Neg(x) => vec![x],
+        Expr::List(items) => items.iter().collect(),
+        Expr::Condition {
+            cond,
+            true_branch,
+            false_branch,
+        } => vec![cond, true_branch, false_branch],
+    }
+}
+
+/// `children`, mutably
+pub fn children_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    match expr {
+        Expr::Atom(Atom::Call { arguments, .. }) => arguments.iter_mut().collect(),
+        Expr::Atom(_) => vec![],
+        Expr::Add(l, r)
+        | Expr::Sub(l, r)
+        | Expr::Mul(l, r)
+        | Expr::Div(l, r)
+        | Expr::Mod(l, r)
+        | Expr::Pow(l, r)
+        | Expr::Compare(_, l, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r)
+        | Expr::Index(l, r) => vec![l, r],
+        Expr::Not(x) | Expr::Neg(x) => vec![x],
+        Expr::List(items) => items.iter_mut().collect(),
+        Expr::Condition {
+            cond,
+            true_branch,
+            false_branch,
+        } => vec![cond, true_branch, false_branch],
+    }
+}
+
+/// How deeply operators, conditions and calls nest in `expr`
+pub fn depth(expr: &Expr) -> usize {
+    1 + children(expr).into_iter().map(depth).max().unwrap_or(0)
+}
+
+/// Names of the cells and params referenced by `expr`
+pub fn identifiers<'a>(expr: &'a Expr, out: &mut BTreeSet<&'a str>) {
+    if let Expr::Atom(Atom::Ident(name)) = expr {
+        out.insert(name);
+    }
+    for child in children(expr) {
+        identifiers(child, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, scanner};
+
+    fn check_code(code: &str, limits: &Limits) -> Vec<Diagnostic> {
+        check(
+            &parser::parse(scanner::scan_spanned(code).unwrap()).unwrap(),
+            limits,
+        )
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_depth: 3,
+            max_dependencies: 2,
+        };
+        assert!(
+            check_code("cell a: 1 + b * c; cell b: max(c, c, c); param c;", &limits).is_empty()
+        );
+
+        let diagnostics = check_code("cell a: 1 + b * -c; param b; param c;", &limits);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(!diagnostics[0].is_error());
+        assert_eq!(
+            diagnostics[0].message,
+            "cell `a` is nested 4 levels deep (limit 3)"
+        );
+        assert_eq!(diagnostics[0].span, Some(0..19));
+
+        let diagnostics = check_code("param x; cell a: x + b + c; param b; param c;", &limits);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "cell `a` depends on 3 cells and params (limit 2)"
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let diagnostics = check_code(
+            "param a; cell b: 1; cell a: 2; cell b: 3;",
+            &Limits::default(),
+        );
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .filter(|x| x.is_error())
+            .map(|x| (x.message.as_str(), x.span.clone(), x.note.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "cell `a` is already defined as a param",
+                    Some(20..30),
+                    Some((0..8, "first defined here".to_string()))
+                ),
+                (
+                    "cell `b` is already defined as a cell",
+                    Some(31..41),
+                    Some((9..19, "first defined here".to_string()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undefined() {
+        let limits = Limits::default();
+        let code = "param usage; cell rate: tier_lookup(usage, rates); \
+                    cell b[t]: if t == 0 ? rate : b[t - 1]; assert rate > 0;";
+        assert!(check_code(code, &limits).is_empty());
+
+        let diagnostics = check_code("param usage; cell a: usag * x; assert y;", &limits);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .filter(|x| x.is_error())
+            .map(|x| (x.message.as_str(), x.hint.as_deref()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "cell `a` references `usag`, which is not defined",
+                    Some("did you mean `usage`?")
+                ),
+                ("cell `a` references `x`, which is not defined", None),
+                ("assertion references `y`, which is not defined", None),
+            ]
+        );
+        assert_eq!(diagnostics[0].span, Some(13..30));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let limits = Limits::default();
+        let code = "cell a: b + 1; cell b: c * 2; cell c: a; cell d: d; cell e: a;";
+        let messages: Vec<_> = check_code(code, &limits)
+            .into_iter()
+            .map(|x| (x.message, x.span))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "cyclic dependency: a -> b -> c -> a".to_string(),
+                    Some(0..14)
+                ),
+                ("cyclic dependency: d -> d".to_string(), Some(41..51)),
+            ]
+        );
+        assert!(check_code(&format!("@iterate {}", code), &limits).is_empty());
+        assert!(check_code("cell a[t]: if t == 0 ? 1 : a[t - 1];", &limits).is_empty());
+    }
+
+    #[test]
+    fn test_unused() {
+        let limits = Limits::default();
+        let code = "param a; param b; cell c: b; cell d: c; output cell e: 1; assert d > 0;";
+        let messages: Vec<_> = check_code(code, &limits)
+            .into_iter()
+            .map(|x| (x.is_error(), x.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![(false, "param `a` is never used".to_string())]
+        );
+
+        let code = "param b; cell c: b; output cell e: 1; cell f[b]: 1;";
+        let messages: Vec<_> = check_code(code, &limits)
+            .into_iter()
+            .map(|x| x.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "cell `c` isn't needed by any output cell or assert",
+                "cell `f` isn't needed by any output cell or assert",
+                "the period `b` of cell `f` shadows the param `b`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let code =
+            "param p; cell a: p; cell b: a * 2; cell c: 3; cell d: c; assert d > 0; cell e: 4;";
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let names: Vec<_> = prune(&ast, &["b"])
+            .nodes
+            .into_iter()
+            .map(|node| match node {
+                Node::Param(param) => param.name,
+                Node::Cell(cell) => cell.name,
+                _ => "assert".to_string(),
+            })
+            .collect();
+        assert_eq!(names, vec!["p", "a", "b", "c", "d", "assert"]);
+    }
+
+    #[test]
+    fn test_private() {
+        assert!(is_private("_rate"));
+        assert!(is_private("pricing._rate"));
+        assert!(!is_private("rate_"));
+        assert!(!is_private("_pricing.rate"));
+
+        assert!(check_code("cell _a: 1; output cell b: _a;", &Limits::default()).is_empty());
+        let diagnostics = check_code("output cell _a: 1;", &Limits::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "private cell `_a` can't be an output"
+        );
+    }
+
+    #[test]
+    fn test_hard_depth() {
+        // the parser already rejects this, an AST built another way doesn't
+        let mut expr = Expr::Atom(Atom::Number(1.0));
+        for _ in 0..150 {
+            expr = Expr::Neg(Box::new(expr));
+        }
+        let ast = AST {
+            nodes: vec![Node::Cell(Cell {
+                name: "a".to_string(),
+                index: None,
+                expr,
+                output: false,
+                doc: None,
+                span: 0..1,
+            })],
+        };
+        let diagnostics = check(&ast, &Limits::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+
+use crate::{
+    ast_interpreter::approx_eq,
+    parser::{Atom, Expr, Node, Operator, AST},
+    sema::{children, children_mut, depth, HARD_MAX_DEPTH},
+    types::{self, Type},
+};
+
+/*
+
+cell a: price * 1 + 0;          ->    cell a: price;
+cell b: if 2 > 1 ? x : y;       ->    cell b: x;
+cell c: !!enabled;              ->    cell c: enabled;
+cell d: 5 + 2;                  ->    cell d: 7;
+cell e: d * 3;                  ->    cell e: 21;
+
+Rewrites run bottom-up on the cells and asserts before evaluation. The
+arithmetic and logic identities only apply when the operand is known to
+be a number or a bool, `provider * 1` still fails if `provider` turns
+out to be a string.
+
+cell fee: base * 0.02;
+cell total: base + fee;         ->    cell total: base + base * 0.02;
+
+A cell referenced by exactly one other cell, and not queried, asserted
+or traced, is inlined into it, unless that nests it too deeply.
+
+cell factor: env("REGION_FACTOR", 1);    ->    cell factor: 1.5;
+
+Environment variables are read once, before anything is evaluated, so
+every permutation sees the same value.
+
+*/
+
+/// Simplifies the expressions of every cell and assert of `ast`. With
+/// `compensated_sum` constant terms of `+` and `-` aren't folded, the sum
+/// of the whole chain is compensated when it's evaluated.
+pub fn simplify(ast: &mut AST, compensated_sum: bool) {
+    let mut known: HashMap<String, Type> = types::check(ast).0.into_iter().collect();
+    let mut eq_tolerance = 0.0;
+    for node in &ast.nodes {
+        match node {
+            Node::Param(param) => {
+                let ty = param.ty.map(Type::from).unwrap_or(Type::Unknown);
+                known.insert(param.name.clone(), ty);
+            }
+            // a bare time-indexed cell is the list of its periods
+            Node::Cell(cell) if cell.index.is_some() => {
+                known.insert(cell.name.clone(), Type::List);
+            }
+            Node::Directive(directive) if directive.name == "eq-tolerance" => {
+                if let [Atom::Number(x)] = directive.arguments[..] {
+                    eq_tolerance = x;
+                }
+            }
+            _ => {}
+        }
+    }
+    let simplifier = Simplifier {
+        known,
+        eq_tolerance,
+        compensated_sum,
+    };
+    // the cells that simplified to a constant, their references are
+    // replaced with it. The cells themselves stay, they can still be queried.
+    let mut constants: HashMap<String, Expr> = HashMap::new();
+    loop {
+        let mut replaced = false;
+        for node in &mut ast.nodes {
+            let (expr, period) = match node {
+                Node::Cell(cell) => (&mut cell.expr, cell.index.as_deref()),
+                Node::Assert(assert) => (&mut assert.expr, None),
+                _ => continue,
+            };
+            replaced |= propagate(expr, &constants, period);
+            simplifier.expr(expr);
+            if let Node::Cell(cell) = node {
+                if cell.index.is_none()
+                    && matches!(
+                        cell.expr,
+                        Expr::Atom(Atom::Number(_) | Atom::String(_) | Atom::Bool(_))
+                    )
+                {
+                    constants.insert(cell.name.clone(), cell.expr.clone());
+                }
+            }
+        }
+        // a cell declared after the cells using it is only known as a
+        // constant in the next round
+        if !replaced {
+            break;
+        }
+    }
+}
+
+/// Replaces the references to `constants` in `expr` with their value, like
+/// `references` finds them. The period of a time-indexed cell shadows a
+/// cell of that name. Whether anything was replaced.
+fn propagate(expr: &mut Expr, constants: &HashMap<String, Expr>, period: Option<&str>) -> bool {
+    match expr {
+        Expr::Atom(Atom::Ident(name)) if period != Some(name.as_str()) => {
+            match constants.get(name) {
+                Some(constant) => {
+                    *expr = constant.clone();
+                    true
+                }
+                None => false,
+            }
+        }
+        Expr::Atom(Atom::Call { name, arguments })
+            if (name == "tier_lookup" || name == "interp") && arguments.len() == 2 =>
+        {
+            let mut replaced = propagate(&mut arguments[0], constants, period);
+            if !matches!(arguments[1], Expr::Atom(Atom::Ident(_))) {
+                replaced |= propagate(&mut arguments[1], constants, period);
+            }
+            replaced
+        }
+        _ => children_mut(expr)
+            .into_iter()
+            .fold(false, |replaced, child| {
+                propagate(child, constants, period) | replaced
+            }),
+    }
+}
+
+/// Replaces every `env("NAME", default)` with the value of the variable,
+/// or `default` when it's unset. Fails on a variable that isn't a number.
+pub fn resolve_env(ast: &mut AST) -> Result<(), anyhow::Error> {
+    for node in &mut ast.nodes {
+        match node {
+            Node::Cell(cell) => {
+                env(&mut cell.expr).map_err(|e| e.context(format!("in cell `{}`", cell.name)))?
+            }
+            Node::Assert(assert) => env(&mut assert.expr)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn env(expr: &mut Expr) -> Result<(), anyhow::Error> {
+    if let Expr::Atom(Atom::Call { name, arguments }) = expr {
+        if name == "env" {
+            let (name, default) = match arguments.as_mut_slice() {
+                [Expr::Atom(Atom::String(name)), default] => (name.clone(), default),
+                _ => bail!("env() expects 2 args: a variable name and a default"),
+            };
+            *expr = match std::env::var(&name) {
+                Ok(value) => match value.trim().parse() {
+                    Ok(x) => Expr::Atom(Atom::Number(x)),
+                    Err(_) => bail!("environment variable {} is not a number: {:?}", name, value),
+                },
+                Err(_) => {
+                    env(default)?;
+                    std::mem::replace(default, Expr::Atom(Atom::Number(0.0)))
+                }
+            };
+            return Ok(());
+        }
+    }
+    for child in children_mut(expr) {
+        env(child)?;
+    }
+    Ok(())
+}
+
+/// Inlines the cells referenced once, by another cell, into that cell,
+/// as long as the result isn't nested deeper than `HARD_MAX_DEPTH`.
+/// `cell_names` are queried and always kept.
+pub fn inline(ast: &mut AST, cell_names: &[&str]) {
+    // a solved cycle is evaluated once per iteration, not once per use
+    if ast
+        .nodes
+        .iter()
+        .any(|node| matches!(node, Node::Directive(x) if x.name == "iterate"))
+    {
+        return;
+    }
+    let mut pinned: HashSet<String> = cell_names.iter().map(|x| x.to_string()).collect();
+    // the nodes using each name, once per occurrence, kept up to date as
+    // cells are inlined
+    let mut uses: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, node) in ast.nodes.iter().enumerate() {
+        let mut names = vec![];
+        match node {
+            Node::Cell(cell) => references(&cell.expr, &mut names),
+            Node::Assert(assert) => {
+                references(&assert.expr, &mut names);
+                pinned.extend(names.into_iter().map(String::from));
+                continue;
+            }
+            _ => continue,
+        }
+        for name in names {
+            uses.entry(name.to_string()).or_default().push(index);
+        }
+    }
+
+    let mut inlined = HashSet::new();
+    for index in 0..ast.nodes.len() {
+        let user = match inlinable(ast, index, &uses, &pinned) {
+            Some(user) => user,
+            None => continue,
+        };
+        let (name, with) = match &ast.nodes[index] {
+            Node::Cell(cell) => (cell.name.clone(), cell.expr.clone()),
+            _ => unreachable!(),
+        };
+        let cell = match &mut ast.nodes[user] {
+            Node::Cell(cell) => cell,
+            _ => unreachable!(),
+        };
+        let mut expr = cell.expr.clone();
+        substitute(&mut expr, &name, &with);
+        if depth(&expr) > HARD_MAX_DEPTH {
+            continue;
+        }
+        cell.expr = expr;
+        inlined.insert(index);
+        uses.remove(&name);
+        let mut names = vec![];
+        references(&with, &mut names);
+        for name in names {
+            for x in uses.get_mut(name).into_iter().flatten() {
+                if *x == index {
+                    *x = user;
+                }
+            }
+        }
+    }
+    let mut index = 0;
+    ast.nodes.retain(|_| {
+        index += 1;
+        !inlined.contains(&(index - 1))
+    });
+}
+
+/// The node index of the cell to inline the cell at `index` into, if it
+/// can be inlined
+fn inlinable(
+    ast: &AST,
+    index: usize,
+    uses: &HashMap<String, Vec<usize>>,
+    pinned: &HashSet<String>,
+) -> Option<usize> {
+    let cell = match &ast.nodes[index] {
+        Node::Cell(cell) if cell.index.is_none() && !pinned.contains(&cell.name) => cell,
+        _ => return None,
+    };
+    let user = match uses.get(&cell.name)?.as_slice() {
+        [user] if *user != index => *user,
+        _ => return None,
+    };
+    // a time-indexed user evaluates its expression once per period
+    let indexed = matches!(&ast.nodes[user], Node::Cell(x) if x.index.is_some());
+    (!indexed && !traces(&cell.expr)).then_some(user)
+}
+
+/// Every reference to a cell or param in `expr`, once per occurrence.
+/// The table names passed to `tier_lookup` and `interp` aren't cells.
+fn references<'a>(expr: &'a Expr, out: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Atom(Atom::Ident(name)) => out.push(name),
+        Expr::Atom(Atom::Call { name, arguments })
+            if (name == "tier_lookup" || name == "interp") && arguments.len() == 2 =>
+        {
+            references(&arguments[0], out);
+            if !matches!(arguments[1], Expr::Atom(Atom::Ident(_))) {
+                references(&arguments[1], out);
+            }
+        }
+        _ => {
+            for child in children(expr) {
+                references(child, out);
+            }
+        }
+    }
+}
+
+/// Replaces the references to `name` in `expr` with `with`, like
+/// `references` finds them
+fn substitute(expr: &mut Expr, name: &str, with: &Expr) {
+    match expr {
+        Expr::Atom(Atom::Ident(x)) if x == name => *expr = with.clone(),
+        Expr::Atom(Atom::Call {
+            name: function,
+            arguments,
+        }) if (function == "tier_lookup" || function == "interp") && arguments.len() == 2 => {
+            substitute(&mut arguments[0], name, with);
+            if !matches!(arguments[1], Expr::Atom(Atom::Ident(_))) {
+                substitute(&mut arguments[1], name, with);
+            }
+        }
+        _ => {
+            for child in children_mut(expr) {
+                substitute(child, name, with);
+            }
+        }
+    }
+}
+
+/// Whether `expr` calls `trace`, which names the cell it's evaluated in
+fn traces(expr: &Expr) -> bool {
+    matches!(expr, Expr::Atom(Atom::Call { name, .. }) if name == "trace")
+        || children(expr).into_iter().any(traces)
+}
+
+struct Simplifier {
+    /// the types of the params and cells
+    known: HashMap<String, Type>,
+    eq_tolerance: f64,
+    compensated_sum: bool,
+}
+
+impl Simplifier {
+    fn expr(&self, expr: &mut Expr) {
+        for child in children_mut(expr) {
+            self.expr(child);
+        }
+        if let Some(simplified) = self.rewrite(expr) {
+            *expr = simplified;
+        }
+    }
+
+    /// The cheaper equivalent of `expr`, whose children are already
+    /// simplified
+    fn rewrite(&self, expr: &Expr) -> Option<Expr> {
+        use Expr::Atom as A;
+        if let Some(x) = self.fold(expr) {
+            return Some(A(Atom::Number(x)));
+        }
+        let simplified = match expr {
+            Expr::Mul(x, one) | Expr::Mul(one, x)
+                if is_number(one, 1.0) && self.is_a(x, Type::Number) =>
+            {
+                x
+            }
+            Expr::Add(x, zero) | Expr::Add(zero, x)
+                if is_number(zero, 0.0) && self.is_a(x, Type::Number) =>
+            {
+                x
+            }
+            Expr::Sub(x, zero) if is_number(zero, 0.0) && self.is_a(x, Type::Number) => x,
+            Expr::Div(x, one) | Expr::Pow(x, one)
+                if is_number(one, 1.0) && self.is_a(x, Type::Number) =>
+            {
+                x
+            }
+            Expr::Neg(x) => match x.as_ref() {
+                Expr::Neg(x) if self.is_a(x, Type::Number) => x,
+                A(Atom::Number(x)) => return Some(A(Atom::Number(-x))),
+                _ => return None,
+            },
+            Expr::Not(x) => match x.as_ref() {
+                Expr::Not(x) if self.is_a(x, Type::Bool) => x,
+                A(Atom::Bool(x)) => return Some(A(Atom::Bool(!x))),
+                _ => return None,
+            },
+            // `&&` and `||` short-circuit, so a constant on the left decides
+            // whether the right is evaluated at all
+            Expr::And(l, r) => match l.as_ref() {
+                A(Atom::Bool(false)) => l,
+                A(Atom::Bool(true)) if self.is_a(r, Type::Bool) => r,
+                _ => return None,
+            },
+            Expr::Or(l, r) => match l.as_ref() {
+                A(Atom::Bool(true)) => l,
+                A(Atom::Bool(false)) if self.is_a(r, Type::Bool) => r,
+                _ => return None,
+            },
+            Expr::Compare(op, l, r) => {
+                return self.compare(op, l, r).map(|x| A(Atom::Bool(x)));
+            }
+            Expr::Condition {
+                cond,
+                true_branch,
+                false_branch,
+            } => match cond.as_ref() {
+                A(Atom::Bool(true)) => true_branch,
+                A(Atom::Bool(false)) => false_branch,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(simplified.as_ref().clone())
+    }
+
+    /// The result of an arithmetic operator on two constants
+    fn fold(&self, expr: &Expr) -> Option<f64> {
+        let (l, r) = match expr {
+            Expr::Add(l, r)
+            | Expr::Sub(l, r)
+            | Expr::Mul(l, r)
+            | Expr::Div(l, r)
+            | Expr::Mod(l, r)
+            | Expr::Pow(l, r) => match (l.as_ref(), r.as_ref()) {
+                (Expr::Atom(Atom::Number(l)), Expr::Atom(Atom::Number(r))) => (*l, *r),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match expr {
+            Expr::Add(..) | Expr::Sub(..) if self.compensated_sum => None,
+            Expr::Add(..) => Some(l + r),
+            Expr::Sub(..) => Some(l - r),
+            Expr::Mul(..) => Some(l * r),
+            Expr::Div(..) => Some(l / r),
+            Expr::Mod(..) => Some(l % r),
+            _ => Some(l.powf(r)),
+        }
+    }
+
+    /// The result of comparing two constants
+    fn compare(&self, op: &Operator, l: &Expr, r: &Expr) -> Option<bool> {
+        let equals = match (l, r) {
+            (Expr::Atom(Atom::Number(l)), Expr::Atom(Atom::Number(r))) => {
+                let (l, r) = (*l, *r);
+                match op {
+                    Operator::Equals | Operator::NotEquals => approx_eq(l, r, self.eq_tolerance),
+                    Operator::Greater => return Some(l > r),
+                    Operator::GreaterEqual => return Some(l >= r),
+                    Operator::Less => return Some(l < r),
+                    Operator::LessEqual => return Some(l <= r),
+                }
+            }
+            (Expr::Atom(Atom::String(l)), Expr::Atom(Atom::String(r))) => l == r,
+            (Expr::Atom(Atom::Bool(l)), Expr::Atom(Atom::Bool(r))) => l == r,
+            _ => return None,
+        };
+        match op {
+            Operator::Equals => Some(equals),
+            Operator::NotEquals => Some(!equals),
+            _ => None,
+        }
+    }
+
+    /// Whether `expr` evaluates to a `ty`, or fails whatever it's
+    /// simplified to
+    fn is_a(&self, expr: &Expr, ty: Type) -> bool {
+        let actual = match expr {
+            Expr::Atom(Atom::Number(_))
+            | Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::Mod(..)
+            | Expr::Pow(..)
+            | Expr::Neg(_) => Type::Number,
+            Expr::Atom(Atom::Bool(_))
+            | Expr::And(..)
+            | Expr::Or(..)
+            | Expr::Not(_)
+            | Expr::Compare(..) => Type::Bool,
+            Expr::Atom(Atom::Ident(name)) => self.known.get(name).copied().unwrap_or(Type::Unknown),
+            _ => Type::Unknown,
+        };
+        actual == ty
+    }
+}
+
+fn is_number(expr: &Expr, value: f64) -> bool {
+    matches!(expr, Expr::Atom(Atom::Number(x)) if *x == value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner;
+
+    fn simplified(code: &str) -> String {
+        let mut ast = crate::parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        simplify(&mut ast, false);
+        ast.to_string()
+    }
+
+    #[test]
+    fn test_simplify() {
+        let code = r#"
+            param a;
+            param n: number;
+            param s: string;
+            param b: bool;
+            cell c1: n * 1 + 0;
+            cell c2: 1 * (n - 0) / 1;
+            cell c3: a * 1;
+            cell c4: s + 0;
+            cell c5: --n;
+            cell c6: !!b;
+            cell c7: if 2 > 1 ? n : a;
+            cell c8: "x" == "y" || b;
+            cell c9: c1 ** 1;
+        "#;
+        let printed = simplified(code);
+        for expected in [
+            "cell c1: n;",
+            "cell c2: n;",
+            "cell c3: a * 1;",
+            "cell c4: s + 0;",
+            "cell c5: n;",
+            "cell c6: b;",
+            "cell c7: n;",
+            "cell c8: b;",
+            "cell c9: c1;",
+        ] {
+            assert!(printed.contains(expected), "{} in {}", expected, printed);
+        }
+    }
+
+    #[test]
+    fn test_resolve_env() {
+        let resolved = |code: &str| {
+            let mut ast = crate::parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+            resolve_env(&mut ast).map(|_| ast.to_string())
+        };
+        std::env::set_var("CELL_SCRIPT_TEST_ENV", "1.5");
+        std::env::set_var("CELL_SCRIPT_TEST_ENV_TEXT", "fast");
+        assert_eq!(
+            resolved(r#"cell a: env("CELL_SCRIPT_TEST_ENV", 1) * 2;"#).unwrap(),
+            "cell a: 1.5 * 2;\n"
+        );
+        assert_eq!(
+            resolved(r#"cell a: env("CELL_SCRIPT_TEST_ENV_UNSET", 1 + 1);"#).unwrap(),
+            "cell a: 1 + 1;\n"
+        );
+        assert_eq!(
+            format!(
+                "{:#}",
+                resolved(r#"cell a: env("CELL_SCRIPT_TEST_ENV_TEXT", 1);"#).unwrap_err()
+            ),
+            "in cell `a`: environment variable CELL_SCRIPT_TEST_ENV_TEXT is not a number: \"fast\""
+        );
+        assert!(resolved(r#"cell a: env("CELL_SCRIPT_TEST_ENV");"#).is_err());
+    }
+
+    #[test]
+    fn test_inline() {
+        let code = r#"
+            param base;
+            cell fee: base * 0.02;
+            cell tax: fee * 2;
+            cell total: base + tax;
+            cell shared: base;
+            cell x: shared + shared;
+            cell asserted: 1;
+            cell y: asserted;
+            cell traced: trace(base);
+            cell z: traced;
+            cell queried: 2;
+            cell w: queried;
+            cell p[t]: fee2;
+            cell fee2: 3;
+            assert asserted > 0;
+        "#;
+        let mut ast = crate::parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        inline(&mut ast, &["total", "x", "y", "z", "queried", "w", "p"]);
+        let printed = ast.to_string();
+        assert!(printed.contains("cell total: base + base * 0.02 * 2;"));
+        for kept in [
+            "cell shared",
+            "cell asserted",
+            "cell traced",
+            "cell queried",
+            "cell fee2",
+        ] {
+            assert!(printed.contains(kept), "{} in {}", kept, printed);
+        }
+        assert!(!printed.contains("cell fee:"));
+        assert!(!printed.contains("cell tax"));
+
+        let mut ast =
+            crate::parser::parse(scanner::scan_spanned("@iterate cell a: b; cell b: 1;").unwrap())
+                .unwrap();
+        inline(&mut ast, &["a"]);
+        assert_eq!(ast.nodes.len(), 3);
+
+        // a long chain is inlined in pieces that stay under the depth limit
+        let mut code = "cell c0: 1;".to_string();
+        for i in 1..4000 {
+            code += &format!("cell c{}: c{} + 1;", i, i - 1);
+        }
+        let mut ast = crate::parser::parse(scanner::scan_spanned(&code).unwrap()).unwrap();
+        inline(&mut ast, &["c3999"]);
+        assert!(ast.nodes.len() < 100, "{} cells left", ast.nodes.len());
+        for node in &ast.nodes {
+            if let Node::Cell(cell) = node {
+                assert!(depth(&cell.expr) <= HARD_MAX_DEPTH);
+            }
+        }
+    }
+
+    #[test]
+    fn test_propagate() {
+        let printed = simplified(
+            r#"
+            param n;
+            cell b: a * 3 + n;
+            cell a: 5 + 2;
+            cell c: if b2 ? "x" : "y";
+            cell b2: a > 1;
+            cell d[a]: a + 1;
+            assert c == "x";
+            "#,
+        );
+        for expected in [
+            "cell b: 21 + n;",
+            "cell a: 7;",
+            "cell c: \"x\";",
+            "cell b2: true;",
+            "cell d[a]: a + 1;",
+            "assert true;",
+        ] {
+            assert!(printed.contains(expected), "{} in {}", expected, printed);
+        }
+
+        // every round substitutes in one walk per expression
+        let mut code = "cell c0: 1;".to_string();
+        for i in 1..4000 {
+            code += &format!("cell c{}: c{} + 1;", i, i - 1);
+        }
+        assert!(simplified(&code).contains("cell c3999: 4000;"));
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        let mut ast = crate::parser::parse(
+            scanner::scan_spanned("cell a: 1e16 + 1 - 1e16 + 2 * 3;").unwrap(),
+        )
+        .unwrap();
+        simplify(&mut ast, true);
+        assert_eq!(
+            ast.to_string(),
+            "cell a: 10000000000000000 + 1 - 10000000000000000 + 6;\n"
+        );
+    }
+
+    #[test]
+    fn test_eq_tolerance() {
+        assert!(simplified("cell a: 1 == 1.05;").contains("cell a: false;"));
+        assert!(simplified("@eq-tolerance(0.1) cell a: 1 == 1.05;").contains("cell a: true;"));
+    }
+}
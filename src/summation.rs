@@ -0,0 +1,137 @@
+/*
+
+Naive f64 accumulation loses the low bits of every small term added to a
+large running total; over a year of hourly costs the drift shows up in the
+cents.
+
+  0.1 + 0.2 + 0.3            0.6000000000000001
+  neumaier([0.1, 0.2, 0.3])  0.6
+  fsum([1e100, 1, -1e100])   1
+
+*/
+
+/// Neumaier's variant of Kahan summation: a running compensation term
+/// recovers the bits lost by each addition.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn add(&mut self, x: f64) {
+        let t = self.sum + x;
+        if self.sum.abs() >= x.abs() {
+            self.compensation += (self.sum - t) + x;
+        } else {
+            self.compensation += (x - t) + self.sum;
+        }
+        self.sum = t;
+    }
+
+    pub fn total(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+/// Correctly rounded sum (Shewchuk's algorithm, as in Python's
+/// `math.fsum`): the result is the exact sum rounded once.
+pub fn fsum(items: impl IntoIterator<Item = f64>) -> f64 {
+    // non-overlapping partial sums, in increasing magnitude
+    let mut partials: Vec<f64> = vec![];
+    // inf and nan inputs can't be tracked exactly, they decide the result
+    let mut special = 0.0;
+    for mut x in items {
+        if !x.is_finite() {
+            special += x;
+            continue;
+        }
+        let mut i = 0;
+        for j in 0..partials.len() {
+            let mut y = partials[j];
+            if x.abs() < y.abs() {
+                std::mem::swap(&mut x, &mut y);
+            }
+            let hi = x + y;
+            if !hi.is_finite() {
+                // the sum overflows
+                return hi + special;
+            }
+            let lo = y - (hi - x);
+            if lo != 0.0 {
+                partials[i] = lo;
+                i += 1;
+            }
+            x = hi;
+        }
+        partials.truncate(i);
+        partials.push(x);
+    }
+
+    if special != 0.0 || special.is_nan() {
+        return special;
+    }
+    let mut hi = match partials.pop() {
+        Some(x) => x,
+        None => return 0.0,
+    };
+    // add the partials from the top down, stopping once they no longer
+    // change the result
+    let mut lo = 0.0;
+    while let Some(y) = partials.pop() {
+        let x = hi;
+        hi = x + y;
+        lo = y - (hi - x);
+        if lo != 0.0 {
+            break;
+        }
+    }
+    // round half-even correctly when the remaining partials push the
+    // result over a halfway point
+    if let Some(next) = partials.last() {
+        if (lo < 0.0 && *next < 0.0) || (lo > 0.0 && *next > 0.0) {
+            let y = lo * 2.0;
+            let x = hi + y;
+            if y == x - hi {
+                hi = x;
+            }
+        }
+    }
+    hi
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compensated() {
+        let mut sum = CompensatedSum::default();
+        for _ in 0..10 {
+            sum.add(0.1);
+        }
+        assert_eq!(sum.total(), 1.0);
+        assert_ne!((0..10).map(|_| 0.1).sum::<f64>(), 1.0);
+
+        let mut sum = CompensatedSum::default();
+        for x in [1.0, 1e100, 1.0, -1e100] {
+            sum.add(x);
+        }
+        assert_eq!(sum.total(), 2.0);
+    }
+
+    #[test]
+    fn test_fsum() {
+        assert_eq!(fsum([]), 0.0);
+        assert_eq!(fsum([0.1; 10]), 1.0);
+        assert_eq!(
+            fsum([1e100, 1.0, -1e100, 1e-100, 1e50, -1.0, -1e50]),
+            1e-100
+        );
+        assert_eq!(fsum([1.0, 1e-16, 1e-16]), 1.0000000000000002);
+        assert_eq!(fsum([f64::MAX, f64::MAX]), f64::INFINITY);
+        assert!(fsum([f64::NAN, 1.0]).is_nan());
+        assert!(fsum([f64::INFINITY, f64::NEG_INFINITY]).is_nan());
+        assert_eq!(fsum([f64::INFINITY, 1.0]), f64::INFINITY);
+    }
+}
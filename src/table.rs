@@ -0,0 +1,193 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/*
+
+# rates.csv
+threshold,rate
+0,0.10
+1000,0.08
+10000,0.05
+
+cell rate: tier_lookup(usage, rates);
+
+# the same table read as a curve: 0.09 halfway between the first two rows
+cell smooth_rate: interp(usage, rates);
+
+*/
+
+/// Field delimiter and decimal separator of csv input. European Excel
+/// locales export `;`-delimited files with `,` decimals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub decimal: char,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            decimal: '.',
+        }
+    }
+}
+
+impl FromStr for CsvDialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "comma" => Ok(Self::default()),
+            "semicolon" => Ok(Self {
+                delimiter: ';',
+                decimal: ',',
+            }),
+            _ => bail!(
+                "unrecognized csv dialect `{}`, expected `comma` or `semicolon`",
+                s
+            ),
+        }
+    }
+}
+
+impl CsvDialect {
+    /// The trimmed fields of a line
+    pub fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        line.split(self.delimiter).map(str::trim).collect()
+    }
+
+    pub fn parse_number(&self, field: &str) -> Option<f64> {
+        if self.decimal == '.' {
+            return field.parse().ok();
+        }
+        if field.contains('.') {
+            return None;
+        }
+        field.replace(self.decimal, ".").parse().ok()
+    }
+}
+
+/// A sorted tier table loaded with `--table name=path.csv`. Each row maps the
+/// lower bound of a tier to its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    thresholds: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl Table {
+    pub fn new(rows: Vec<(f64, f64)>) -> Result<Self, anyhow::Error> {
+        if rows.is_empty() {
+            bail!("table has no rows");
+        }
+        for window in rows.windows(2) {
+            if window[0].0 >= window[1].0 {
+                bail!(
+                    "table thresholds must be strictly increasing ({} comes before {})",
+                    window[0].0,
+                    window[1].0
+                );
+            }
+        }
+        let (thresholds, values) = rows.into_iter().unzip();
+        Ok(Self { thresholds, values })
+    }
+
+    /// Parses `threshold,value` rows. Empty lines, `#` comments and a
+    /// header row are skipped.
+    pub fn parse_csv(content: &str, dialect: CsvDialect) -> Result<Self, anyhow::Error> {
+        let mut rows = vec![];
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (threshold, value) = match dialect.split(line)[..] {
+                [threshold, value] => (threshold, value),
+                _ => bail!(
+                    "line {}: expected `threshold{}value`",
+                    i + 1,
+                    dialect.delimiter
+                ),
+            };
+            match (dialect.parse_number(threshold), dialect.parse_number(value)) {
+                (Some(threshold), Some(value)) => rows.push((threshold, value)),
+                _ if rows.is_empty() => { /* header */ }
+                _ => bail!("line {}: invalid number in `{}`", i + 1, line),
+            }
+        }
+        Self::new(rows)
+    }
+
+    /// Value of the last tier whose threshold is `<= x`, or `None` when `x`
+    /// is below the first tier.
+    pub fn lookup(&self, x: f64) -> Option<f64> {
+        let idx = self.thresholds.partition_point(|threshold| *threshold <= x);
+        idx.checked_sub(1).map(|idx| self.values[idx])
+    }
+
+    /// Reads the rows as points of a curve and linearly interpolates `x`
+    /// between the two around it. Outside the curve it's the first or the
+    /// last value.
+    pub fn interpolate(&self, x: f64) -> f64 {
+        let idx = self.thresholds.partition_point(|threshold| *threshold <= x);
+        if idx == 0 {
+            return self.values[0];
+        }
+        if idx == self.thresholds.len() {
+            return self.values[idx - 1];
+        }
+        let (x0, x1) = (self.thresholds[idx - 1], self.thresholds[idx]);
+        let (y0, y1) = (self.values[idx - 1], self.values[idx]);
+        y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let table = Table::parse_csv(
+            "threshold,rate\n0,10\n100,8\n\n# top tier\n1000,5\n",
+            CsvDialect::default(),
+        )
+        .unwrap();
+        assert_eq!(table.lookup(-1.0), None);
+        assert_eq!(table.lookup(0.0), Some(10.0));
+        assert_eq!(table.lookup(99.9), Some(10.0));
+        assert_eq!(table.lookup(100.0), Some(8.0));
+        assert_eq!(table.lookup(1e9), Some(5.0));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let table = Table::new(vec![(0.0, 10.0), (100.0, 30.0), (200.0, 0.0)]).unwrap();
+        assert_eq!(table.interpolate(-5.0), 10.0);
+        assert_eq!(table.interpolate(50.0), 20.0);
+        assert_eq!(table.interpolate(100.0), 30.0);
+        assert_eq!(table.interpolate(150.0), 15.0);
+        assert_eq!(table.interpolate(1e9), 0.0);
+    }
+
+    #[test]
+    fn test_dialect() {
+        let dialect: CsvDialect = "semicolon".parse().unwrap();
+        let table = Table::parse_csv("threshold;rate\n0;0,5\n1000,5;0,25\n", dialect).unwrap();
+        assert_eq!(table.lookup(1000.0), Some(0.5));
+        assert_eq!(table.lookup(1000.5), Some(0.25));
+        assert_eq!(dialect.parse_number("1.5"), None);
+        assert!("tab".parse::<CsvDialect>().is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Table::parse_csv("", CsvDialect::default()).is_err());
+        assert!(Table::parse_csv("0,1\n0,2\n", CsvDialect::default()).is_err());
+        assert!(Table::parse_csv("0,1\nx,2\n", CsvDialect::default()).is_err());
+        assert!(Table::parse_csv("0;1\n", CsvDialect::default()).is_err());
+    }
+}
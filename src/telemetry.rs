@@ -0,0 +1,95 @@
+use std::{sync::OnceLock, time::Duration};
+
+use anyhow::anyhow;
+
+/*
+
+Only compiled with `--features telemetry`. Nothing is collected until an
+embedder installs a sink:
+
+struct Log;
+
+impl telemetry::Sink for Log {
+    fn event(&self, event: &telemetry::Event) {
+        eprintln!("{:?}", event);
+    }
+}
+
+telemetry::set_sink(Log)?;
+
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// a model was parsed into `nodes` declarations
+    Parsed { nodes: usize, duration: Duration },
+    /// `cells` were queried from a model, evaluating `expressions`
+    /// expressions on the way
+    Evaluated {
+        engine: &'static str,
+        cells: usize,
+        expressions: usize,
+        duration: Duration,
+    },
+}
+
+pub trait Sink: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+static SINK: OnceLock<Box<dyn Sink>> = OnceLock::new();
+
+/// Installs the process-wide sink. It can only be installed once.
+pub fn set_sink(sink: impl Sink + 'static) -> Result<(), anyhow::Error> {
+    SINK.set(Box::new(sink))
+        .map_err(|_| anyhow!("a telemetry sink is already installed"))
+}
+
+/// Builds and sends the event, only if a sink is installed
+pub(crate) fn emit(event: impl FnOnce() -> Event) {
+    if let Some(sink) = SINK.get() {
+        sink.event(&event());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::*;
+    use crate::{ast_interpreter, parser, scanner};
+
+    static EVENTS: Mutex<Vec<Event>> = Mutex::new(vec![]);
+
+    struct Collect;
+
+    impl Sink for Collect {
+        fn event(&self, event: &Event) {
+            EVENTS.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        set_sink(Collect).unwrap();
+        assert!(set_sink(Collect).is_err());
+
+        let code = "cell a: 1 + 2; cell b: a * 2;";
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        ast_interpreter::run(&ast, &["b"], &HashMap::new()).unwrap();
+
+        let events = EVENTS.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Parsed { nodes: 2, .. })));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::Evaluated {
+                engine: "ast",
+                cells: 1,
+                expressions: 6,
+                ..
+            }
+        )));
+    }
+}
@@ -0,0 +1,340 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    diagnostic::Diagnostic,
+    parser::{Atom, Cell, Expr, Node, Operator, ParamType, AST},
+    sema::children,
+};
+
+/*
+
+param provider: string;
+cell base: if provider == "gcp" ? 10 : 20;   # number
+cell label: provider + 1;                    # error: `+` expects numbers, found string
+
+Every cell gets the type of its expression. Params without a declared
+type, and whatever depends on them in a way that can't be told
+statically, are `unknown` and only checked when the model runs.
+
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Number,
+    String,
+    Bool,
+    List,
+    Unknown,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Bool => "bool",
+            Type::List => "list",
+            Type::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<ParamType> for Type {
+    fn from(ty: ParamType) -> Self {
+        match ty {
+            ParamType::Number => Type::Number,
+            ParamType::String => Type::String,
+            ParamType::Bool => Type::Bool,
+        }
+    }
+}
+
+/// The type of every cell, in declaration order, and an error for every
+/// cell whose expression can't evaluate because of the types of its
+/// operands
+pub fn check(ast: &AST) -> (Vec<(String, Type)>, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        params: HashMap::new(),
+        cells: HashMap::new(),
+        types: HashMap::new(),
+        period: None,
+    };
+    for node in &ast.nodes {
+        match node {
+            Node::Param(param) => {
+                let ty = param.ty.map(Type::from).unwrap_or(Type::Unknown);
+                checker.params.insert(&param.name, ty);
+            }
+            Node::Cell(cell) => {
+                checker.cells.insert(&cell.name, cell);
+            }
+            _ => {}
+        }
+    }
+
+    let mut types = vec![];
+    let mut diagnostics = vec![];
+    for node in &ast.nodes {
+        let (error, span) = match node {
+            Node::Cell(cell) => {
+                let (ty, error) = checker.cell(&cell.name);
+                types.push((cell.name.clone(), ty));
+                (
+                    error.map(|e| format!("cell `{}`: {}", cell.name, e)),
+                    &cell.span,
+                )
+            }
+            Node::Assert(assert) => {
+                let (ty, error) = match checker.expr(&assert.expr) {
+                    Ok(ty) => (ty, None),
+                    Err(e) => (Type::Unknown, Some(e)),
+                };
+                let error = match (error, ty) {
+                    (Some(e), _) => Some(e),
+                    (None, Type::Bool | Type::Unknown) => None,
+                    (None, ty) => Some(format!("expected a bool, found {}", ty)),
+                };
+                (error.map(|e| format!("assertion: {}", e)), &assert.span)
+            }
+            _ => continue,
+        };
+        if let Some(error) = error {
+            diagnostics.push(Diagnostic::new(error).with_span(span.clone()));
+        }
+    }
+    (types, diagnostics)
+}
+
+struct Checker<'a> {
+    params: HashMap<&'a str, Type>,
+    cells: HashMap<&'a str, &'a Cell>,
+    /// inferred cells, `None` while a cell is being inferred so cycles end
+    /// up `unknown`
+    types: HashMap<&'a str, Option<(Type, Option<String>)>>,
+    /// the period of the time-indexed cell being inferred
+    period: Option<&'a str>,
+}
+
+impl<'a> Checker<'a> {
+    /// The type of a cell, and the first type error in its expression
+    fn cell(&mut self, name: &'a str) -> (Type, Option<String>) {
+        match self.types.get(name) {
+            Some(Some(result)) => return result.clone(),
+            Some(None) => return (Type::Unknown, None),
+            None => {}
+        }
+        let cell = self.cells[name];
+        self.types.insert(name, None);
+        let period = std::mem::replace(&mut self.period, cell.index.as_deref());
+        let result = match self.expr(&cell.expr) {
+            Ok(ty) => (ty, None),
+            Err(e) => (Type::Unknown, Some(e)),
+        };
+        self.period = period;
+        self.types.insert(name, Some(result.clone()));
+        result
+    }
+
+    /// The type of a cell as a dependency, its errors are reported on the
+    /// cell itself
+    fn reference(&mut self, name: &'a str) -> Type {
+        if self.period == Some(name) {
+            return Type::Number;
+        }
+        if let Some(ty) = self.params.get(name) {
+            return *ty;
+        }
+        match self.cells.get(name) {
+            // without an index, all the periods
+            Some(cell) if cell.index.is_some() => Type::List,
+            Some(_) => self.cell(name).0,
+            None => Type::Unknown,
+        }
+    }
+
+    fn expect(&mut self, expr: &'a Expr, expected: Type, what: &str) -> Result<(), String> {
+        match self.expr(expr)? {
+            Type::Unknown => Ok(()),
+            ty if ty == expected => Ok(()),
+            ty => Err(format!("{} expects {}s, found {}", what, expected, ty)),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Result<Type, String> {
+        let ty = match expr {
+            Expr::Atom(Atom::Number(_)) => Type::Number,
+            Expr::Atom(Atom::String(_)) => Type::String,
+            Expr::Atom(Atom::Bool(_)) => Type::Bool,
+            Expr::Atom(Atom::Ident(name)) => self.reference(name),
+            Expr::Atom(Atom::Call { name, arguments }) => {
+                let mut argument_types = vec![];
+                for argument in arguments {
+                    argument_types.push(self.expr(argument)?);
+                }
+                match name.as_str() {
+                    "approx_eq" => Type::Bool,
+                    "trace" => argument_types.first().copied().unwrap_or(Type::Unknown),
+                    _ => Type::Number,
+                }
+            }
+            Expr::Add(l, r)
+            | Expr::Sub(l, r)
+            | Expr::Mul(l, r)
+            | Expr::Div(l, r)
+            | Expr::Mod(l, r)
+            | Expr::Pow(l, r) => {
+                let op = match expr {
+                    Expr::Add(..) => "`+`",
+                    Expr::Sub(..) => "`-`",
+                    Expr::Mul(..) => "`*`",
+                    Expr::Div(..) => "`/`",
+                    Expr::Mod(..) => "`%`",
+                    _ => "`**`",
+                };
+                self.expect(l, Type::Number, op)?;
+                self.expect(r, Type::Number, op)?;
+                Type::Number
+            }
+            Expr::Neg(x) => {
+                self.expect(x, Type::Number, "`-`")?;
+                Type::Number
+            }
+            Expr::And(l, r) | Expr::Or(l, r) => {
+                let op = if matches!(expr, Expr::And(..)) {
+                    "`&&`"
+                } else {
+                    "`||`"
+                };
+                self.expect(l, Type::Bool, op)?;
+                self.expect(r, Type::Bool, op)?;
+                Type::Bool
+            }
+            Expr::Not(x) => {
+                self.expect(x, Type::Bool, "`!`")?;
+                Type::Bool
+            }
+            Expr::Compare(op, l, r) => {
+                if let Operator::Equals | Operator::NotEquals = op {
+                    match (self.expr(l)?, self.expr(r)?) {
+                        (Type::Unknown, _) | (_, Type::Unknown) => {}
+                        (l, r) if l == r => {}
+                        (l, r) => return Err(format!("cannot compare {} with {}", l, r)),
+                    }
+                } else {
+                    let op = format!("`{}`", op);
+                    self.expect(l, Type::Number, &op)?;
+                    self.expect(r, Type::Number, &op)?;
+                }
+                Type::Bool
+            }
+            Expr::Condition {
+                cond,
+                true_branch,
+                false_branch,
+            } => {
+                self.expect(cond, Type::Bool, "`if`")?;
+                match (self.expr(true_branch)?, self.expr(false_branch)?) {
+                    (l, r) if l == r => l,
+                    _ => Type::Unknown,
+                }
+            }
+            Expr::List(_) => {
+                for child in children(expr) {
+                    self.expr(child)?;
+                }
+                Type::List
+            }
+            Expr::Index(list, index) => {
+                self.expect(index, Type::Number, "an index")?;
+                match list.as_ref() {
+                    Expr::Atom(Atom::Ident(name))
+                        if self.period != Some(name.as_str())
+                            && matches!(self.cells.get(name.as_str()), Some(cell) if cell.index.is_some()) =>
+                    {
+                        self.cell(name).0
+                    }
+                    _ => {
+                        self.expect(list, Type::List, "indexing")?;
+                        Type::Unknown
+                    }
+                }
+            }
+        };
+        Ok(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, scanner};
+
+    fn check_code(code: &str) -> (Vec<(String, Type)>, Vec<String>) {
+        let ast = parser::parse(scanner::scan_spanned(code).unwrap()).unwrap();
+        let (types, diagnostics) = check(&ast);
+        (types, diagnostics.into_iter().map(|x| x.message).collect())
+    }
+
+    #[test]
+    fn test_infer() {
+        let (types, errors) = check_code(
+            r#"
+            param provider: string;
+            param usage;
+            cell a: if provider == "gcp" ? 10 : 20;
+            cell b: a > 1 && !true;
+            cell c: [a, b];
+            cell d: c[0];
+            cell e: usage * 2;
+            cell f[t]: if t == 0 ? 1 : f[t - 1] * 2;
+            cell g: f;
+            cell h: f[2];
+            cell i: if b ? "x" : 1;
+            "#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let types: Vec<_> = types.into_iter().map(|(_, ty)| ty).collect();
+        assert_eq!(
+            types,
+            vec![
+                Type::Number,
+                Type::Bool,
+                Type::List,
+                Type::Unknown,
+                Type::Number,
+                Type::Number,
+                Type::List,
+                Type::Number,
+                Type::Unknown,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let (_, errors) = check_code(
+            r#"
+            param provider: string;
+            param flag: bool;
+            cell a: provider + 1;
+            cell b: if 1 ? 2 : 3;
+            cell c: provider == 1;
+            cell d: a * 2;
+            cell e: !flag && flag;
+            cell f: "x" > "y";
+            assert 1 + 1;
+            "#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "cell `a`: `+` expects numbers, found string",
+                "cell `b`: `if` expects bools, found number",
+                "cell `c`: cannot compare string with number",
+                "cell `f`: `>` expects numbers, found string",
+                "assertion: expected a bool, found number",
+            ]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+/*
+
+# cellspace.toml
+[tables]
+rates = "tables/rates.csv"
+
+[models.infra]
+path = "infra.cell"
+
+[models.pricing]
+path = "pricing.cell"
+links = { infra = "infra.cell" }
+query = "total"
+params = ["usage=100,1000"]
+
+*/
+
+/// The name of the manifest `cell-script run` looks for by default
+pub const MANIFEST: &str = "cellspace.toml";
+
+/// A manifest listing the models of a repository, the tables they share and
+/// the default scenario (query and params) of each model. Paths are
+/// relative to the directory of the manifest.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    #[serde(skip)]
+    root: PathBuf,
+    /// tables loaded for every model, as with `--table name=path.csv`
+    #[serde(default)]
+    pub tables: IndexMap<String, PathBuf>,
+    #[serde(default)]
+    pub models: IndexMap<String, WorkspaceModel>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceModel {
+    pub path: PathBuf,
+    /// alias -> path of the models linked as with `--link "path as alias"`
+    #[serde(default)]
+    pub links: IndexMap<String, PathBuf>,
+    /// default `--query`
+    pub query: Option<String>,
+    /// default `--param`s, e.g. `"usage=100,1000"`
+    #[serde(default)]
+    pub params: Vec<String>,
+}
+
+impl Workspace {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            anyhow::Error::new(e).context(format!("cannot read `{}`", path.display()))
+        })?;
+        let root = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        Self::parse(&content, root)
+            .map_err(|e| e.context(format!("invalid workspace `{}`", path.display())))
+    }
+
+    pub fn parse(content: &str, root: PathBuf) -> Result<Self, anyhow::Error> {
+        let mut workspace: Workspace = toml::from_str(content)?;
+        workspace.root = root;
+        Ok(workspace)
+    }
+
+    pub fn model(&self, name: &str) -> Result<&WorkspaceModel, anyhow::Error> {
+        match self.models.get(name) {
+            Some(model) => Ok(model),
+            None => bail!(
+                "model `{}` is not in the workspace. available models: {}",
+                name,
+                self.models.keys().join(", ")
+            ),
+        }
+    }
+
+    /// Resolves a path of the manifest relative to its directory
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let workspace = Workspace::parse(
+            r#"
+            [tables]
+            rates = "tables/rates.csv"
+
+            [models.infra]
+            path = "infra.cell"
+
+            [models.pricing]
+            path = "pricing.cell"
+            links = { infra = "infra.cell" }
+            query = "total"
+            params = ["usage=100,1000"]
+            "#,
+            PathBuf::from("models"),
+        )
+        .unwrap();
+        assert_eq!(workspace.tables.len(), 1);
+        let pricing = workspace.model("pricing").unwrap();
+        assert_eq!(pricing.query.as_deref(), Some("total"));
+        assert_eq!(pricing.params, vec!["usage=100,1000"]);
+        assert_eq!(
+            workspace.resolve(&pricing.links["infra"]),
+            PathBuf::from("models/infra.cell")
+        );
+        assert_eq!(workspace.model("infra").unwrap().query, None);
+
+        let error = workspace.model("billing").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "model `billing` is not in the workspace. available models: infra, pricing"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Workspace::parse("[models.a]\nquery = \"x\"", PathBuf::new()).is_err());
+        assert!(
+            Workspace::parse("[models.a]\npath = \"a.cell\"\nparam = []", PathBuf::new()).is_err()
+        );
+
+        // the cause is kept under the path
+        let error = Workspace::load(Path::new("missing.toml")).unwrap_err();
+        assert_eq!(error.to_string(), "cannot read `missing.toml`");
+        assert_eq!(error.chain().count(), 2);
+        let path = std::env::temp_dir().join(format!("cell-script-ws-{}.toml", std::process::id()));
+        std::fs::write(&path, "[models\n").unwrap();
+        let error = Workspace::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("invalid workspace `{}`", path.display())
+        );
+        assert_eq!(error.chain().count(), 2);
+    }
+}